@@ -0,0 +1,377 @@
+use std::io::prelude::*;
+use std::fs::File;
+use std::fmt;
+use std::error;
+use std::io;
+use std::fs;
+use std::path::Path;
+
+use mbc::{GBMbc, GBRomOnly, GBMbc1, GBMbc2, GBMbc3, GBMbc5, MBC2_RAM_SIZE};
+
+// References:
+// - http://gbdev.gg8.se/wiki/articles/The_Cartridge_Header
+
+// The header lives in 0100-014F, so anything smaller can't be a rom
+const HEADER_END: usize = 0x150;
+
+#[derive(Debug)]
+pub enum CartridgeError {
+    Io(io::Error),
+    // the file is smaller than the header or than the rom size declared in it
+    Truncated { expected: usize, found: usize },
+    InvalidRomSize(u8),
+    InvalidRamSize(u8),
+    // memory bank controller not emulated
+    UnsupportedType(u8),
+    HeaderChecksum { expected: u8, found: u8 },
+}
+
+impl fmt::Display for CartridgeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            CartridgeError::Io(ref err) => write!(f, "Could not read the rom: {}", err),
+            CartridgeError::Truncated{ expected, found } =>
+                write!(f, "Truncated rom: expected {} bytes, found {}", expected, found),
+            CartridgeError::InvalidRomSize(code) => write!(f, "Invalid rom size code: 0x{:02X}", code),
+            CartridgeError::InvalidRamSize(code) => write!(f, "Invalid ram size code: 0x{:02X}", code),
+            CartridgeError::UnsupportedType(code) => write!(f, "Unsupported cartridge type: 0x{:02X}", code),
+            CartridgeError::HeaderChecksum{ expected, found } =>
+                write!(f, "Header checksum mismatch: expected 0x{:02X}, found 0x{:02X}", expected, found),
+        }
+    }
+}
+
+impl error::Error for CartridgeError {}
+
+impl From<io::Error> for CartridgeError {
+    fn from(err: io::Error) -> CartridgeError {
+        CartridgeError::Io(err)
+    }
+}
+
+#[derive(Debug)]
+pub struct CartridgeHeader {
+    pub title: String,
+    pub cgb_flag: u8, // 0x80 - cgb compatible, 0xC0 - cgb only
+    pub sgb_flag: bool,
+    pub cartridge_type: u8,
+    pub rom_size: usize, // in bytes
+    pub ram_size: usize, // in bytes
+    pub licensee: String,
+    pub version: u8,
+    pub header_checksum: u8,
+    pub global_checksum: u16,
+}
+
+impl CartridgeHeader {
+
+    fn parse(rom: &[u8]) -> Result<CartridgeHeader, CartridgeError> {
+        if rom.len() < HEADER_END {
+            return Err(CartridgeError::Truncated{ expected: HEADER_END, found: rom.len() });
+        }
+
+        let cgb_flag = rom[0x143];
+
+        // in cgb cartridges the last bytes of the title are the manufacturer code and the cgb flag
+        let title_end = if cgb_flag & 0x80 != 0 { 0x13F } else { 0x144 };
+        let title = rom[0x134..title_end].iter()
+            .take_while(|&&c| c != 0)
+            .map(|&c| c as char)
+            .collect::<String>();
+
+        // 32KB << N
+        let rom_size = match rom[0x148] {
+            code @ 0x00..=0x08 => (32 * 1024) << code,
+            code => return Err(CartridgeError::InvalidRomSize(code)),
+        };
+
+        let ram_size = match rom[0x149] {
+            0x00 => 0,
+            0x01 => 2 * 1024,
+            0x02 => 8 * 1024,
+            0x03 => 32 * 1024,
+            0x04 => 128 * 1024,
+            0x05 => 64 * 1024,
+            code => return Err(CartridgeError::InvalidRamSize(code)),
+        };
+
+        // 0x33 means the new licensee code (0144-0145, two ascii chars) is used instead
+        let licensee = if rom[0x14B] == 0x33 {
+            rom[0x144..0x146].iter().map(|&c| c as char).collect::<String>()
+        } else {
+            format!("{:02X}", rom[0x14B])
+        };
+
+        Ok(CartridgeHeader{
+            title,
+            cgb_flag,
+            sgb_flag: rom[0x146] == 0x03,
+            cartridge_type: rom[0x147],
+            rom_size,
+            ram_size,
+            licensee,
+            version: rom[0x14C],
+            header_checksum: rom[0x14D],
+            global_checksum: ((rom[0x14E] as u16) << 8) | (rom[0x14F] as u16),
+        })
+    }
+
+    pub fn is_cgb_only(&self) -> bool {
+        self.cgb_flag == 0xC0
+    }
+
+    // The external ram (and the mbc3 clock) is kept by a battery
+    pub fn has_battery(&self) -> bool {
+        matches!(self.cartridge_type, 0x03 | 0x06 | 0x09 | 0x0D | 0x0F | 0x10 | 0x13 | 0x1B | 0x1E | 0x22 | 0xFF)
+    }
+
+}
+
+pub struct Cartridge {
+    header: CartridgeHeader,
+    mbc: Box<dyn GBMbc>,
+    rom: Vec<u8>,
+    ram: Vec<u8>,
+    // the hardware never checks it, so it's only reported
+    global_checksum_valid: bool,
+    // the ram changed since the last time it was saved
+    ram_dirty: bool,
+}
+
+impl Cartridge {
+
+    pub fn from_file(filename: &str) -> Result<Cartridge, CartridgeError> {
+        let mut f = File::open(filename)?;
+        let mut rom = vec!();
+        f.read_to_end(&mut rom)?;
+
+        Cartridge::from_bytes(rom)
+    }
+
+    pub fn from_bytes(mut rom: Vec<u8>) -> Result<Cartridge, CartridgeError> {
+        let header = CartridgeHeader::parse(&rom)?;
+
+        if rom.len() < header.rom_size {
+            return Err(CartridgeError::Truncated{ expected: header.rom_size, found: rom.len() });
+        }
+
+        // x = x - rom[i] - 1 over 0134-014C. The boot rom locks up if it doesn't match
+        let header_checksum = rom[0x134..0x14D].iter()
+            .fold(0u8, |x, &byte| x.wrapping_sub(byte).wrapping_sub(1));
+        if header_checksum != header.header_checksum {
+            return Err(CartridgeError::HeaderChecksum{ expected: header.header_checksum, found: header_checksum });
+        }
+
+        // sum of every byte of the rom, except the checksum itself. Many hacks and homebrew roms
+        // get it wrong and still run on the hardware, so it doesn't stop the rom from loading
+        let global_checksum = rom[..header.rom_size].iter()
+            .enumerate()
+            .filter(|&(pos, _)| pos != 0x14E && pos != 0x14F)
+            .fold(0u16, |sum, (_, &byte)| sum.wrapping_add(byte as u16));
+        let global_checksum_valid = global_checksum == header.global_checksum;
+
+        // some dumps are padded
+        rom.truncate(header.rom_size);
+
+        let mbc: Box<dyn GBMbc> = match header.cartridge_type {
+            // rom only, rom+ram, rom+ram+battery
+            0x00 | 0x08 | 0x09 => Box::new(GBRomOnly),
+            // mbc1, mbc1+ram, mbc1+ram+battery
+            0x01..=0x03 => Box::new(GBMbc1::new()),
+            // mbc2, mbc2+battery
+            0x05 | 0x06 => Box::new(GBMbc2::new()),
+            // mbc3+timer+battery, mbc3+timer+ram+battery
+            0x0F | 0x10 => Box::new(GBMbc3::new(true)),
+            // mbc3, mbc3+ram, mbc3+ram+battery
+            0x11..=0x13 => Box::new(GBMbc3::new(false)),
+            // mbc5, mbc5+ram, mbc5+ram+battery
+            0x19..=0x1B => Box::new(GBMbc5::new(false)),
+            // mbc5+rumble, mbc5+rumble+ram, mbc5+rumble+ram+battery
+            0x1C..=0x1E => Box::new(GBMbc5::new(true)),
+            code => return Err(CartridgeError::UnsupportedType(code)),
+        };
+
+        // the mbc2 ram is built into the controller, so the header reports no ram
+        let ram = match header.cartridge_type {
+            0x05 | 0x06 => vec![0; MBC2_RAM_SIZE],
+            _ => vec![0; header.ram_size],
+        };
+
+        Ok(Cartridge{
+            header,
+            mbc,
+            rom,
+            ram,
+            global_checksum_valid,
+            ram_dirty: false,
+        })
+    }
+
+    pub fn get_header(&self) -> &CartridgeHeader {
+        &self.header
+    }
+
+    pub fn is_global_checksum_valid(&self) -> bool {
+        self.global_checksum_valid
+    }
+
+    pub fn is_rumbling(&self) -> bool {
+        self.mbc.is_rumbling()
+    }
+
+    // Battery backed data: the external ram followed by the controller state (e.g. the rtc).
+    // Same layout as the .sav files of other emulators
+    pub fn get_save_data(&self) -> Vec<u8> {
+        let mut data = self.ram.clone();
+        data.extend(self.mbc.save_state());
+        data
+    }
+
+    pub fn load_save_data(&mut self, data: &[u8]) {
+        let ram_size = self.ram.len().min(data.len());
+        self.ram[..ram_size].copy_from_slice(&data[..ram_size]);
+        self.mbc.load_state(&data[ram_size..]);
+    }
+
+    // Loads the battery backed ram from a .sav file. A missing file just means
+    // there's nothing saved yet
+    pub fn load_save_file(&mut self, filename: &Path) -> io::Result<()> {
+        if !self.header.has_battery() {
+            return Ok(());
+        }
+
+        let mut f = match File::open(filename) {
+            Ok(f) => f,
+            Err(ref err) if err.kind() == io::ErrorKind::NotFound => return Ok(()),
+            Err(err) => return Err(err),
+        };
+
+        let mut data = vec!();
+        f.read_to_end(&mut data)?;
+        self.load_save_data(&data);
+        self.ram_dirty = false;
+
+        Ok(())
+    }
+
+    // Writes the .sav file if the ram changed since the last flush. Carts with a clock are always
+    // written, the rtc state changes without any ram write
+    pub fn flush_save_file(&mut self, filename: &Path) -> io::Result<()> {
+        if !self.header.has_battery() || !(self.ram_dirty || self.mbc.has_rtc()) {
+            return Ok(());
+        }
+
+        // write to a temporary file first, so a crash while saving doesn't corrupt the previous save
+        let tmp_filename = filename.with_extension("sav.tmp");
+        {
+            let mut f = File::create(&tmp_filename)?;
+            f.write_all(&self.get_save_data())?;
+            f.sync_all()?;
+        }
+        fs::rename(&tmp_filename, filename)?;
+        self.ram_dirty = false;
+
+        Ok(())
+    }
+
+    // 0000-7FFF
+    pub fn read_rom(&self, pos: usize) -> u8 {
+        self.mbc.read_rom(&self.rom, pos)
+    }
+
+    // 0000-7FFF. Handled by the memory bank controller, the rom itself is read only
+    pub fn write_rom(&mut self, pos: usize, byte: u8) {
+        self.mbc.write_rom(pos, byte);
+    }
+
+    // A000-BFFF
+    pub fn read_ram(&self, pos: usize) -> u8 {
+        self.mbc.read_ram(&self.ram, pos)
+    }
+
+    // A000-BFFF. Writes ignored by the controller (e.g. ram disabled) don't need to be saved
+    pub fn write_ram(&mut self, pos: usize, byte: u8) {
+        if self.mbc.write_ram(&mut self.ram, pos, byte) {
+            self.ram_dirty = true;
+        }
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+
+    use std::env;
+    use std::fs;
+    use std::process;
+
+    use super::{Cartridge, CartridgeError};
+
+    // 32KB rom with valid checksums
+    fn test_rom(cartridge_type: u8, ram_code: u8) -> Vec<u8> {
+        let mut rom = vec![0; 32 * 1024];
+        rom[0x134..0x138].copy_from_slice(b"TEST");
+        rom[0x147] = cartridge_type;
+        rom[0x149] = ram_code;
+
+        rom[0x14D] = rom[0x134..0x14D].iter()
+            .fold(0u8, |x, &byte| x.wrapping_sub(byte).wrapping_sub(1));
+
+        let global_checksum = rom.iter().fold(0u16, |sum, &byte| sum.wrapping_add(byte as u16));
+        rom[0x14E] = (global_checksum >> 8) as u8;
+        rom[0x14F] = global_checksum as u8;
+
+        rom
+    }
+
+    #[test]
+    fn global_checksum_mismatch_is_not_fatal() {
+        let cartridge = Cartridge::from_bytes(test_rom(0x00, 0x00)).unwrap();
+        assert!(cartridge.is_global_checksum_valid());
+
+        let mut rom = test_rom(0x00, 0x00);
+        rom[0x14F] ^= 0xFF;
+        let cartridge = Cartridge::from_bytes(rom).unwrap();
+        assert!(!cartridge.is_global_checksum_valid());
+    }
+
+    #[test]
+    fn header_checksum_mismatch_is_fatal() {
+        let mut rom = test_rom(0x00, 0x00);
+        rom[0x14D] ^= 0xFF;
+        match Cartridge::from_bytes(rom) {
+            Err(CartridgeError::HeaderChecksum{ .. }) => {},
+            _ => panic!("expected a header checksum error"),
+        }
+    }
+
+    #[test]
+    fn ignored_ram_writes_are_not_saved() {
+        // mbc1+ram+battery, 8KB
+        let mut cartridge = Cartridge::from_bytes(test_rom(0x03, 0x02)).unwrap();
+
+        // ram disabled
+        cartridge.write_ram(0xA000, 0x12);
+        assert!(!cartridge.ram_dirty);
+
+        cartridge.write_rom(0x0000, 0x0A);
+        cartridge.write_ram(0xA000, 0x12);
+        assert!(cartridge.ram_dirty);
+        assert_eq!(cartridge.read_ram(0xA000), 0x12);
+    }
+
+    #[test]
+    fn rtc_is_saved_without_ram() {
+        // mbc3+timer+battery, no ram
+        let mut cartridge = Cartridge::from_bytes(test_rom(0x0F, 0x00)).unwrap();
+        assert!(!cartridge.ram_dirty);
+
+        let filename = env::temp_dir().join(format!("rust-gameboy-rtc-{}.sav", process::id()));
+        cartridge.flush_save_file(&filename).unwrap();
+
+        let saved = fs::read(&filename);
+        fs::remove_file(&filename).ok();
+        assert_eq!(saved.unwrap().len(), 48);
+    }
+
+}
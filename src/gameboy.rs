@@ -0,0 +1,133 @@
+use std::io;
+use std::path::Path;
+
+use cpu::GBCpu;
+use mem::GBMem;
+use gpu::{GBGpu, GBRenderer, CYCLES_PER_FRAME};
+use joypad::{GBJoypad, GBButton};
+use cartridge::Cartridge;
+
+// Entry point for the library users. Ties the cpu, the memory and the gpu together
+pub struct GameBoy {
+
+    cpu: GBCpu,
+    gpu: GBGpu,
+
+}
+
+impl GameBoy {
+
+    pub fn new() -> GameBoy {
+        GameBoy{
+            cpu: GBCpu::new(GBMem::new()),
+            gpu: GBGpu::new(),
+        }
+    }
+
+    // The boot rom is mapped over 0000-00FF and runs before the cartridge code
+    pub fn load_boot_rom(&mut self, boot_rom: &[u8]) {
+        self.cpu.get_mem_mut().load_boot_rom(boot_rom);
+    }
+
+    pub fn load_cartridge(&mut self, cartridge: Cartridge) {
+        self.cpu.get_mem_mut().load_cartridge(cartridge);
+    }
+
+    // Starts from the cartridge entry point (0x100) with the state the boot rom leaves behind.
+    // Call it after loading the cartridge
+    pub fn skip_boot(&mut self) {
+        self.cpu.skip_boot();
+    }
+
+    // Battery backed ram. Nothing happens for cartridges without a battery
+    pub fn load_save_file(&mut self, filename: &Path) -> io::Result<()> {
+        match self.cpu.get_mem_mut().get_cartridge_mut() {
+            Some(cartridge) => cartridge.load_save_file(filename),
+            None => Ok(()),
+        }
+    }
+
+    pub fn flush_save_file(&mut self, filename: &Path) -> io::Result<()> {
+        match self.cpu.get_mem_mut().get_cartridge_mut() {
+            Some(cartridge) => cartridge.flush_save_file(filename),
+            None => Ok(()),
+        }
+    }
+
+    // Rumble motor of MBC5 rumble cartridges
+    pub fn is_rumbling(&self) -> bool {
+        match self.cpu.get_mem_ref().get_cartridge_ref() {
+            Some(cartridge) => cartridge.is_rumbling(),
+            None => false,
+        }
+    }
+
+    // Executes a single instruction and clocks the timer, the DMA and the gpu with its cycles.
+    // Returns true if a frame was completed
+    pub fn step(&mut self) -> bool {
+        self.cpu.step();
+
+        let cycles = self.cpu.get_last_op_cycles();
+        self.cpu.get_mem_mut().step(cycles);
+
+        self.gpu.step(&mut self.cpu)
+    }
+
+    // Runs until the next frame is completed. If the lcd doesn't complete a frame
+    // (e.g. the cpu is stopped) it returns after a frame worth of cycles
+    pub fn run_frame(&mut self) {
+        let mut cycles = 0;
+        while cycles < CYCLES_PER_FRAME {
+            let frame_done = self.step();
+            cycles += self.cpu.get_last_op_cycles();
+
+            if frame_done {
+                break;
+            }
+
+            // nothing is being clocked (STOP)
+            if self.cpu.get_last_op_cycles() == 0 {
+                break;
+            }
+        }
+    }
+
+    // Scanline (default) or pixel fifo rendering. Can be changed while running
+    pub fn set_renderer(&mut self, renderer: GBRenderer) {
+        self.gpu.set_renderer(renderer);
+    }
+
+    // RGB24 160x144 framebuffer
+    pub fn get_framebuffer(&self) -> &[u8] {
+        self.gpu.get_framebuffer()
+    }
+
+    pub fn set_input(&mut self, button: GBButton, pressed: bool) {
+        self.cpu.get_mem_mut().set_button(button, pressed);
+    }
+
+    pub fn get_cpu_ref(&self) -> &GBCpu {
+        &self.cpu
+    }
+
+    pub fn get_cpu_mut(&mut self) -> &mut GBCpu {
+        &mut self.cpu
+    }
+
+    pub fn get_gpu_ref(&self) -> &GBGpu {
+        &self.gpu
+    }
+
+    pub fn get_joypad_ref(&self) -> &GBJoypad {
+        self.cpu.get_mem_ref().get_joypad_ref()
+    }
+
+}
+
+impl Default for GameBoy {
+
+    fn default() -> GameBoy {
+        GameBoy::new()
+    }
+
+}
@@ -1,11 +1,88 @@
-use mem::GBMem;
 use cpu::GBCpu;
-use sdl_display::SDLDisplay;
+
+mod fifo;
+
+use self::fifo::GBPixelFifo;
 
 // References:
 // - http://imrannazar.com/GameBoy-Emulation-in-JavaScript:-GPU-Timings
 // - http://imrannazar.com/GameBoy-Emulation-in-JavaScript:-Graphics
 
+pub const SCREEN_WIDTH: usize = 160;
+pub const SCREEN_HEIGHT: usize = 144;
+
+// Cycles it takes the lcd to draw a full frame (154 lines * 456 cycles)
+pub const CYCLES_PER_FRAME: usize = 70224;
+
+// LCDC (0xff40) bits
+const LCDC_BG_ENABLE: u8 = 0x01;
+const LCDC_OBJ_ENABLE: u8 = 0x02;
+const LCDC_OBJ_SIZE: u8 = 0x04; // 0 - 8x8, 1 - 8x16
+const LCDC_BG_TILE_MAP: u8 = 0x08; // 0 - 9800-9BFF, 1 - 9C00-9FFF
+const LCDC_TILE_DATA: u8 = 0x10; // 0 - 8800-97FF (signed index), 1 - 8000-8FFF
+const LCDC_WINDOW_ENABLE: u8 = 0x20;
+const LCDC_WINDOW_TILE_MAP: u8 = 0x40; // 0 - 9800-9BFF, 1 - 9C00-9FFF
+const LCDC_LCD_ENABLE: u8 = 0x80;
+
+// STAT (0xff41) bits
+const STAT_MODE: u8 = 0x03; // 0 - hblank, 1 - vblank, 2 - oam, 3 - vram
+const STAT_COINCIDENCE: u8 = 0x04; // LY = LYC
+const STAT_HBLANK_INT: u8 = 0x08;
+const STAT_VBLANK_INT: u8 = 0x10;
+const STAT_OAM_INT: u8 = 0x20;
+const STAT_LYC_INT: u8 = 0x40;
+
+// OAM attributes (byte 3)
+const OBJ_BG_PRIORITY: u8 = 0x80; // drawn behind the background colors 1-3
+const OBJ_Y_FLIP: u8 = 0x40;
+const OBJ_X_FLIP: u8 = 0x20;
+const OBJ_PALETTE: u8 = 0x10; // 0 - OBP0, 1 - OBP1
+
+// the hardware can only draw 10 sprites per line
+const MAX_SPRITES_PER_LINE: usize = 10;
+
+// RGB of each of the 4 shades of gray, from white to black
+const SHADES: [[u8; 3]; 4] = [
+    [0xFF, 0xFF, 0xFF],
+    [0xAA, 0xAA, 0xAA],
+    [0x55, 0x55, 0x55],
+    [0x00, 0x00, 0x00],
+];
+
+// How the lines are drawn
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum GBRenderer {
+    // the whole line at once, at the end of the VRAM mode. It always takes 172 cycles
+    Scanline,
+    // pixel by pixel. The VRAM mode takes longer with fine scrolling, the window and sprites
+    PixelFifo,
+}
+
+// Sprite selected by the OAM scan. The scan latches its position, the tile and the attributes
+// are read from OAM when the sprite is drawn
+#[derive(Clone, Copy)]
+struct GBLineSprite {
+    oam: usize, // address of the OAM entry
+    y: usize,
+    x: usize,
+}
+
+// Row of the sprite (at OAM Y) drawn in the line, or None if it isn't in the line. The sprite
+// size can change after the OAM scan, so this is checked again when the sprite is drawn
+fn sprite_row(line: usize, y: usize, height: usize, attributes: u8) -> Option<usize> {
+    // the sprite Y is the screen Y + 16
+    let row = match (line + 16).checked_sub(y) {
+        Some(row) if row < height => row,
+        _ => return None,
+    };
+
+    if attributes & OBJ_Y_FLIP != 0 {
+        Some(height - 1 - row)
+    } else {
+        Some(row)
+    }
+}
+
 enum GBGpuMode {
     HBLANK,
     VBLANK,
@@ -15,9 +92,23 @@ enum GBGpuMode {
 
 pub struct GBGpu {
 
+    renderer: GBRenderer,
+    fifo: GBPixelFifo,
     mode: GBGpuMode,
+    lcd_on: bool,
+    // or of the enabled STAT interrupt sources. The interrupt is requested on its rising edge
+    stat_line: bool,
     cycles: usize,
+    // cycles since the beginning of the line (pixel fifo only)
+    line_cycles: usize,
     drawing_line: usize,
+    // internal line counter of the window. It only advances on lines where the window was drawn
+    window_line: usize,
+    // LY matched WY at some point in this frame. Changing WY after that doesn't hide the window
+    window_triggered: bool,
+    // sprites in the current line, sorted by priority
+    line_sprites: Vec<GBLineSprite>,
+    framebuffer: Vec<u8>, // RGB24
 
 }
 
@@ -25,82 +116,629 @@ impl GBGpu {
 
     pub fn new() -> GBGpu {
         GBGpu{
+            renderer: GBRenderer::Scanline,
+            fifo: GBPixelFifo::new(),
             mode: GBGpuMode::HBLANK,
+            lcd_on: false,
+            stat_line: false,
             cycles: 0,
+            line_cycles: 0,
             drawing_line: 0,
+            window_line: 0,
+            window_triggered: false,
+            line_sprites: Vec::with_capacity(MAX_SPRITES_PER_LINE),
+            framebuffer: vec![0xFF; SCREEN_WIDTH * SCREEN_HEIGHT * 3],
         }
     }
 
-    pub fn step(&mut self, cpu: &mut GBCpu, display: &mut SDLDisplay) {
+    pub fn get_framebuffer(&self) -> &[u8] {
+        &self.framebuffer
+    }
+
+    pub fn get_renderer(&self) -> GBRenderer {
+        self.renderer
+    }
+
+    // Can be switched at any time, the current line starts over
+    pub fn set_renderer(&mut self, renderer: GBRenderer) {
+        self.renderer = renderer;
+
+        if self.lcd_on {
+            self.cycles = 0;
+            self.line_cycles = 0;
+            if self.drawing_line < SCREEN_HEIGHT {
+                self.mode = GBGpuMode::OAM;
+            }
+        }
+    }
+
+    // Returns true when a frame was completed (entered vblank)
+    pub fn step(&mut self, cpu: &mut GBCpu) -> bool {
 
         self.cycles += cpu.get_last_op_cycles();
 
+        if cpu.get_mem_ref().get_io(0xff40) & LCDC_LCD_ENABLE == 0 {
+            if self.lcd_on {
+                self.turn_off();
+            }
+
+            // the screen stays blank, but the frames keep coming at the same rate
+            let mut frame_done = false;
+            if self.cycles >= CYCLES_PER_FRAME {
+                self.cycles -= CYCLES_PER_FRAME;
+                frame_done = true;
+            }
+
+            self.update_stat(cpu);
+            return frame_done;
+        }
+
+        // turning the lcd on starts a new frame from the first line
+        if !self.lcd_on {
+            self.lcd_on = true;
+            self.mode = GBGpuMode::OAM;
+            self.cycles = 0;
+            self.line_cycles = 0;
+        }
+
+        let frame_done = match self.renderer {
+            GBRenderer::Scanline => self.step_scanline(cpu),
+            GBRenderer::PixelFifo => self.step_fifo(cpu),
+        };
+
+        self.update_stat(cpu);
+
+        frame_done
+    }
+
+    fn step_scanline(&mut self, cpu: &mut GBCpu) -> bool {
+
+        let mut frame_done = false;
+
         match self.mode {
-            // TODO: check 0xff41 (stat flags)
             // just rendered a line, going back to the left side of the screen
             GBGpuMode::HBLANK => {
                 // HBLANK duration in cycles: 204
                 if self.cycles >= 204 {
-
-                    self.cycles = 0;
-                    self.drawing_line += 1;
-                    // LY mem register. It stores the current line
-                    cpu.set_memreg_ly(self.drawing_line as u8);
-
-                    // check if we reached the last line. If so, enter vblank and draw the frame
-                    if self.drawing_line == 143 {
-                        // Enter vblank
-                        // is VBLANK interrupt enabled?
-                        if cpu.is_interrupt_enabled(0) {
-                            // request vblank interrupt
-                            cpu.set_interrupt_request(0, true);
-                        }
-                        self.mode = GBGpuMode::VBLANK;
-                        // TODO: draw the frame. SDL(?)
-                    } else {
-                        // just one more line, start reading the sprites
-                        self.mode = GBGpuMode::OAM;
-                    }
-
+                    self.cycles -= 204;
+                    frame_done = self.next_line(cpu);
                 }
-
             },
             GBGpuMode::VBLANK => {
-                // VBLANK duration: 456 * 10 lines
+                // VBLANK duration: 456 * 10 lines (144-153)
                 if self.cycles >= 456 {
-                    self.cycles = 0;
-                    self.drawing_line += 1;
-                    // LY mem register. It stores the current line
-                    cpu.set_memreg_ly(self.drawing_line as u8);
-
-                    if self.drawing_line > 153 {
-                        // Restart scanning modes
-                        self.mode = GBGpuMode::OAM;
-                        self.drawing_line = 0;
-                        // LY mem register. It stores the current line
-                        cpu.set_memreg_ly(self.drawing_line as u8);
-                    }
+                    self.cycles -= 456;
+                    self.next_line(cpu);
                 }
             },
             GBGpuMode::OAM => {
                 // loop for a while in the OAM mode and go to the VRAM mode after
                 if self.cycles >= 80 {
-                    self.cycles = 0;
+                    self.cycles -= 80;
                     self.mode = GBGpuMode::VRAM;
+
+                    // the sprites of this line
+                    self.scan_oam(cpu);
                 }
             },
             GBGpuMode::VRAM => {
                 // loop for a while in the OAM mode and then writes the new line to the buffer
                 if self.cycles >= 172 {
-                    self.cycles = 0;
+                    self.cycles -= 172;
                     self.mode = GBGpuMode::HBLANK;
 
                     // Write a scanline to the framebuffer
-                    // TODO: draw line
+                    self.render_line(cpu);
                 }
             },
         }
 
+        frame_done
+    }
+
+    // Runs the cycles one by one. The VRAM mode lasts until the fifo pushed the 160 pixels,
+    // and the HBLANK takes the rest of the 456 cycles of the line
+    fn step_fifo(&mut self, cpu: &mut GBCpu) -> bool {
+
+        let mut frame_done = false;
+
+        while self.cycles > 0 {
+            self.cycles -= 1;
+            self.line_cycles += 1;
+
+            match self.mode {
+                GBGpuMode::OAM => {
+                    if self.line_cycles == 80 {
+                        self.mode = GBGpuMode::VRAM;
+
+                        self.scan_oam(cpu);
+                        self.check_window_trigger(cpu);
+                        self.fifo.start_line(cpu, self.drawing_line, self.window_line, self.window_triggered, &self.line_sprites);
+                    }
+                },
+                GBGpuMode::VRAM => {
+                    if let Some((x, palette, color)) = self.fifo.tick(cpu) {
+                        let line = self.drawing_line;
+                        self.set_pixel(x, line, palette, color);
+                    }
+
+                    if self.fifo.is_done() {
+                        self.mode = GBGpuMode::HBLANK;
+                        if self.fifo.is_window_drawn() {
+                            self.window_line += 1;
+                        }
+                    }
+                },
+                GBGpuMode::HBLANK | GBGpuMode::VBLANK => {},
+            }
+
+            if self.line_cycles == 456 {
+                self.line_cycles = 0;
+                frame_done |= self.next_line(cpu);
+            }
+        }
+
+        frame_done
+    }
+
+    // Moves to the next line, at the end of the HBLANK or of a VBLANK line.
+    // Returns true when a frame was completed (entered vblank)
+    fn next_line(&mut self, cpu: &mut GBCpu) -> bool {
+        self.drawing_line += 1;
+
+        // check if we reached the last line. If so, enter vblank and draw the frame
+        if self.drawing_line == SCREEN_HEIGHT {
+            // Enter vblank
+            // request vblank interrupt. The request is always flagged, IE is checked by the cpu
+            cpu.set_interrupt_request(0, true);
+            self.mode = GBGpuMode::VBLANK;
+            return true;
+        }
+
+        if self.drawing_line > 153 {
+            // Restart scanning modes
+            self.mode = GBGpuMode::OAM;
+            self.drawing_line = 0;
+            self.window_line = 0;
+            self.window_triggered = false;
+        } else if self.drawing_line < SCREEN_HEIGHT {
+            // just one more line, start reading the sprites
+            self.mode = GBGpuMode::OAM;
+        }
+
+        false
+    }
+
+    // LCDC bit 7 cleared: LY is reset and the screen goes blank until it's turned on again
+    fn turn_off(&mut self) {
+        self.lcd_on = false;
+        self.mode = GBGpuMode::HBLANK;
+        self.cycles = 0;
+        self.line_cycles = 0;
+        self.drawing_line = 0;
+        self.window_line = 0;
+        self.window_triggered = false;
+
+        for byte in self.framebuffer.iter_mut() {
+            *byte = 0xFF;
+        }
+    }
+
+    // Updates LY and STAT, requesting the STAT interrupt (bit 1) when one of the enabled sources
+    // becomes active. The sources are or'ed in a single line, so while one of them is active
+    // the others can't request a new interrupt (STAT blocking)
+    fn update_stat(&mut self, cpu: &mut GBCpu) {
+        // LY mem register. It stores the current line, and also updates the coincidence flag
+        cpu.set_memreg_ly(self.drawing_line as u8);
+
+        let mode = match self.mode {
+            GBGpuMode::HBLANK => 0,
+            GBGpuMode::VBLANK => 1,
+            GBGpuMode::OAM => 2,
+            GBGpuMode::VRAM => 3,
+        };
+
+        let stat = (cpu.get_mem_ref().get_io(0xff41) & !STAT_MODE) | mode;
+        cpu.get_mem_mut().set_io(0xff41, stat);
+
+        if !self.lcd_on {
+            self.stat_line = false;
+            return;
+        }
+
+        let stat_line = (stat & STAT_HBLANK_INT != 0 && mode == 0)
+            || (stat & STAT_VBLANK_INT != 0 && mode == 1)
+            || (stat & STAT_OAM_INT != 0 && mode == 2)
+            || (stat & STAT_LYC_INT != 0 && stat & STAT_COINCIDENCE != 0);
+
+        if stat_line && !self.stat_line {
+            cpu.set_interrupt_request(1, true);
+        }
+        self.stat_line = stat_line;
+    }
+
+    fn render_line(&mut self, cpu: &GBCpu) {
+        let line = self.drawing_line;
+        if line >= SCREEN_HEIGHT {
+            return;
+        }
+
+        self.check_window_trigger(cpu);
+
+        let mem = cpu.get_mem_ref();
+        let lcdc = mem.get_io(0xff40);
+
+        // color number (0-3) of each pixel, before the palette
+        let mut colors = [0u8; SCREEN_WIDTH];
+
+        // with the background disabled it's just white (color 0)
+        if lcdc & LCDC_BG_ENABLE != 0 {
+            let scy = mem.get_io(0xff42) as usize;
+            let scx = mem.get_io(0xff43) as usize;
+            let map = if lcdc & LCDC_BG_TILE_MAP != 0 { 0x9c00 } else { 0x9800 };
+
+            // the background is 256x256 and wraps around
+            let y = (scy + line) & 0xff;
+            for (x, color) in colors.iter_mut().enumerate() {
+                *color = self.tile_pixel(cpu, lcdc, map, (scx + x) & 0xff, y);
+            }
+
+            self.render_window_line(cpu, lcdc, &mut colors);
+        }
+
+        let bgp = mem.get_io(0xff47);
+        for (x, &color) in colors.iter().enumerate() {
+            self.set_pixel(x, line, bgp, color);
+        }
+
+        if lcdc & LCDC_OBJ_ENABLE != 0 {
+            self.render_sprites_line(cpu, lcdc, &colors);
+        }
+    }
+
+    // The window shows up from the line where LY matches WY
+    fn check_window_trigger(&mut self, cpu: &GBCpu) {
+        if self.drawing_line == cpu.get_mem_ref().get_io(0xff4a) as usize {
+            self.window_triggered = true;
+        }
+    }
+
+    // Selects the first 10 sprites (in OAM order) that overlap the current line
+    fn scan_oam(&mut self, cpu: &GBCpu) {
+        let mem = cpu.get_mem_ref();
+        let height = if mem.get_io(0xff40) & LCDC_OBJ_SIZE != 0 { 16 } else { 8 };
+        let line = self.drawing_line + 16; // the sprite Y is the screen Y + 16
+
+        self.line_sprites.clear();
+        for sprite in 0..40 {
+            let oam = 0xfe00 + sprite * 4;
+            let y = mem.get_oam(oam) as usize;
+            if line >= y && line < y + height {
+                self.line_sprites.push(GBLineSprite{ oam, y, x: mem.get_oam(oam + 1) as usize });
+                if self.line_sprites.len() == MAX_SPRITES_PER_LINE {
+                    break;
+                }
+            }
+        }
+
+        // DMG priority: the smallest X wins, the first in OAM in case of a tie. The sort is stable
+        self.line_sprites.sort_by_key(|sprite| sprite.x);
+    }
+
+    fn render_sprites_line(&mut self, cpu: &GBCpu, lcdc: u8, colors: &[u8; SCREEN_WIDTH]) {
+        let mem = cpu.get_mem_ref();
+        let height = if lcdc & LCDC_OBJ_SIZE != 0 { 16 } else { 8 };
+        let line = self.drawing_line;
+
+        // the pixel of each column is taken from the sprite with the highest priority,
+        // if it isn't transparent there
+        let mut drawn = [false; SCREEN_WIDTH];
+
+        for i in 0..self.line_sprites.len() {
+            let GBLineSprite{ oam, y, x } = self.line_sprites[i];
+            let attributes = mem.get_oam(oam + 3);
+
+            // in 8x16 mode the bit 0 of the tile is ignored: the top tile is even, the bottom one odd
+            let tile = if height == 16 { mem.get_oam(oam + 2) & 0xfe } else { mem.get_oam(oam + 2) };
+
+            let row = match sprite_row(line, y, height, attributes) {
+                Some(row) => row,
+                None => continue,
+            };
+
+            // sprites always use the 8000-8FFF tile data
+            let row_addr = 0x8000 + (tile as usize) * 16 + row * 2;
+            let low = mem.get_vram(row_addr);
+            let high = mem.get_vram(row_addr + 1);
+
+            let palette = if attributes & OBJ_PALETTE != 0 { mem.get_io(0xff49) } else { mem.get_io(0xff48) };
+
+            for col in 0..8 {
+                // the sprite X is the screen X + 8
+                let screen_x = x + col;
+                if !(8..SCREEN_WIDTH + 8).contains(&screen_x) || drawn[screen_x - 8] {
+                    continue;
+                }
+                let screen_x = screen_x - 8;
+
+                let bit = if attributes & OBJ_X_FLIP != 0 { col } else { 7 - col };
+                let color = (((high >> bit) & 0x1) << 1) | ((low >> bit) & 0x1);
+
+                // color 0 is transparent, the sprites below can still show up
+                if color == 0 {
+                    continue;
+                }
+                drawn[screen_x] = true;
+
+                // the background priority only hides the sprite behind the colors 1-3
+                if attributes & OBJ_BG_PRIORITY != 0 && colors[screen_x] != 0 {
+                    continue;
+                }
+
+                self.set_pixel(screen_x, line, palette, color);
+            }
+        }
+    }
+
+    // The window is drawn over the background, starting at (WX - 7, WY). It doesn't scroll
+    fn render_window_line(&mut self, cpu: &GBCpu, lcdc: u8, colors: &mut [u8; SCREEN_WIDTH]) {
+        let mem = cpu.get_mem_ref();
+        let wx = mem.get_io(0xff4b) as usize;
+
+        // WX > 166 is off screen
+        if lcdc & LCDC_WINDOW_ENABLE == 0 || !self.window_triggered || wx > 166 {
+            return;
+        }
+
+        let map = if lcdc & LCDC_WINDOW_TILE_MAP != 0 { 0x9c00 } else { 0x9800 };
+        for (x, color) in colors.iter_mut().enumerate() {
+            // with WX < 7 the window starts at the left edge, but its first 7 - WX pixels are cut off
+            if x + 7 < wx {
+                continue;
+            }
+            *color = self.tile_pixel(cpu, lcdc, map, x + 7 - wx, self.window_line);
+        }
+
+        self.window_line += 1;
+    }
+
+    // Color number of the pixel (x, y) of a 32x32 tiles map
+    fn tile_pixel(&self, cpu: &GBCpu, lcdc: u8, map: usize, x: usize, y: usize) -> u8 {
+        let mem = cpu.get_mem_ref();
+        let tile = mem.get_vram(map + (y / 8) * 32 + (x / 8));
+
+        let tile_addr = if lcdc & LCDC_TILE_DATA != 0 {
+            0x8000 + (tile as usize) * 16
+        } else {
+            // tiles 0-127 are at 9000-97FF and 128-255 at 8800-8FFF
+            (0x9000 + (tile as i8 as isize) * 16) as usize
+        };
+
+        // 2 bytes per row: the first one has the low bits of the colors, the second the high bits
+        let row = tile_addr + (y % 8) * 2;
+        let low = mem.get_vram(row);
+        let high = mem.get_vram(row + 1);
+
+        let bit = 7 - (x % 8);
+        (((high >> bit) & 0x1) << 1) | ((low >> bit) & 0x1)
+    }
+
+    // Maps the color number through the palette (2 bits per color) to the framebuffer
+    fn set_pixel(&mut self, x: usize, y: usize, palette: u8, color: u8) {
+        let shade = (palette >> (color * 2)) & 0x3;
+        let pos = (y * SCREEN_WIDTH + x) * 3;
+        self.framebuffer[pos..pos + 3].copy_from_slice(&SHADES[shade as usize]);
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+
+    use cpu::GBCpu;
+    use mem::GBMem;
+    use gameboy::GameBoy;
+    use super::{GBGpu, GBGpuMode, GBRenderer, SCREEN_WIDTH};
+
+    // Shade of a pixel of the framebuffer (0 - white, 3 - black)
+    fn shade(gpu: &GBGpu, x: usize, y: usize) -> u8 {
+        match gpu.get_framebuffer()[(y * SCREEN_WIDTH + x) * 3] {
+            0xFF => 0,
+            0xAA => 1,
+            0x55 => 2,
+            _ => 3,
+        }
+    }
+
+    #[test]
+    fn sprite_moved_after_the_oam_scan() {
+        let mut cpu = GBCpu::new(GBMem::new());
+        let mut gpu = GBGpu::new();
+
+        {
+            let mem = cpu.get_mem_mut();
+            mem.put(0xFF40, 0x93); // lcd, tile data 8000, sprites and bg on
+            mem.put(0xFF47, 0xE4);
+            mem.put(0xFF48, 0xE4);
+            // tile 1 is all color 3, the background (tile 0) all color 0
+            for pos in 0x8010..0x8020 {
+                mem.put(pos, 0xFF);
+            }
+            mem.put(0xFE00, 26); // sprite 0 in lines 10-17, columns 12-19
+            mem.put(0xFE01, 20);
+            mem.put(0xFE02, 1);
+        }
+
+        gpu.drawing_line = 10;
+        gpu.scan_oam(&cpu);
+        assert_eq!(gpu.line_sprites[0].oam, 0xFE00);
+
+        // moved out of the line after the scan, it's still drawn at the latched position
+        cpu.get_mem_mut().put(0xFE00, 40);
+        cpu.get_mem_mut().put(0xFE01, 100);
+        gpu.render_line(&cpu);
+
+        for x in 0..SCREEN_WIDTH {
+            let expected = if (12..20).contains(&x) { 3 } else { 0 };
+            assert_eq!(shade(&gpu, x, 10), expected, "x {}", x);
+        }
+    }
+
+    // Returns and clears the STAT interrupt request (bit 1)
+    fn take_stat_interrupt(cpu: &mut GBCpu) -> bool {
+        let requests = cpu.get_mem_ref().get_io(0xFF0F);
+        cpu.get_mem_mut().set_io(0xFF0F, requests & !0x02);
+        requests & 0x02 != 0
+    }
+
+    #[test]
+    fn stat_interrupt_blocking() {
+        let mut cpu = GBCpu::new(GBMem::new());
+        let mut gpu = GBGpu::new();
+        gpu.lcd_on = true;
+        cpu.get_mem_mut().put(0xFF45, 5);
+        cpu.get_mem_mut().put(0xFF41, 0x48); // LYC and HBLANK sources
+
+        gpu.drawing_line = 5;
+        gpu.mode = GBGpuMode::OAM;
+        gpu.update_stat(&mut cpu);
+        assert!(take_stat_interrupt(&mut cpu));
+
+        // the LYC source keeps the line high, so entering HBLANK doesn't request it again
+        gpu.mode = GBGpuMode::VRAM;
+        gpu.update_stat(&mut cpu);
+        gpu.mode = GBGpuMode::HBLANK;
+        gpu.update_stat(&mut cpu);
+        assert!(!take_stat_interrupt(&mut cpu));
+
+        // next line: both sources are off, then HBLANK raises the line again
+        gpu.drawing_line = 6;
+        gpu.mode = GBGpuMode::OAM;
+        gpu.update_stat(&mut cpu);
+        gpu.mode = GBGpuMode::VRAM;
+        gpu.update_stat(&mut cpu);
+        assert!(!take_stat_interrupt(&mut cpu));
+
+        gpu.mode = GBGpuMode::HBLANK;
+        gpu.update_stat(&mut cpu);
+        assert!(take_stat_interrupt(&mut cpu));
+    }
+
+    #[test]
+    fn lyc_coincidence() {
+        let mut cpu = GBCpu::new(GBMem::new());
+        let mut gpu = GBGpu::new();
+        gpu.lcd_on = true;
+        gpu.mode = GBGpuMode::OAM;
+        cpu.get_mem_mut().put(0xFF45, 3);
+
+        // the flag is updated without the interrupt source enabled
+        gpu.drawing_line = 2;
+        gpu.update_stat(&mut cpu);
+        assert_eq!(cpu.get_mem_ref().get(0xFF44), 2);
+        assert_eq!(cpu.get_mem_ref().get(0xFF41) & 0x04, 0x00);
+
+        gpu.drawing_line = 3;
+        gpu.update_stat(&mut cpu);
+        assert_eq!(cpu.get_mem_ref().get(0xFF41) & 0x04, 0x04);
+        assert!(!take_stat_interrupt(&mut cpu));
+
+        // and it's read only
+        cpu.get_mem_mut().put(0xFF41, 0x00);
+        assert_eq!(cpu.get_mem_ref().get(0xFF41) & 0x04, 0x04);
+
+        gpu.drawing_line = 4;
+        gpu.update_stat(&mut cpu);
+        cpu.get_mem_mut().put(0xFF41, 0x40);
+        assert!(!take_stat_interrupt(&mut cpu));
+
+        gpu.drawing_line = 3;
+        gpu.update_stat(&mut cpu);
+        assert!(take_stat_interrupt(&mut cpu));
+    }
+
+    #[test]
+    fn lcd_off_resets_ly_and_stat() {
+        let mut cpu = GBCpu::new(GBMem::new());
+        let mut gpu = GBGpu::new();
+        gpu.lcd_on = true;
+        gpu.drawing_line = 50;
+        gpu.mode = GBGpuMode::VRAM;
+        cpu.get_mem_mut().put(0xFF41, 0x40); // LYC source, LYC = 0
+        gpu.update_stat(&mut cpu);
+        assert_eq!(cpu.get_mem_ref().get(0xFF41) & 0x03, 3);
+
+        cpu.get_mem_mut().put(0xFF40, 0x00);
+        gpu.step(&mut cpu);
+
+        // LY = 0 and mode 0. LY matches LYC, but nothing is requested while the lcd is off
+        assert_eq!(cpu.get_mem_ref().get(0xFF44), 0);
+        assert_eq!(cpu.get_mem_ref().get(0xFF41) & 0x07, 0x04);
+        assert!(!gpu.stat_line);
+        assert!(!take_stat_interrupt(&mut cpu));
+
+        // turning it back on starts from the OAM mode of line 0
+        cpu.get_mem_mut().put(0xFF40, 0x80);
+        gpu.step(&mut cpu);
+        assert_eq!(cpu.get_mem_ref().get(0xFF41) & 0x03, 2);
+        assert!(take_stat_interrupt(&mut cpu));
+    }
+
+    // Second full frame drawn with the given renderer (the first one starts mid-way)
+    fn render_frame(renderer: GBRenderer, scx: u8, wx: u8, lcdc: u8) -> Vec<u8> {
+        let mut gameboy = GameBoy::new();
+        // JR -2
+        gameboy.load_boot_rom(&[0x18, 0xFE]);
+        gameboy.set_renderer(renderer);
+
+        {
+            let mem = gameboy.get_cpu_mut().get_mem_mut();
+            for pos in 0..0x100 {
+                mem.put(0x8000 + pos, ((pos * 37) ^ (pos >> 3)) as u8);
+            }
+            for pos in 0..0x400 {
+                mem.put(0x9800 + pos, (pos % 13) as u8);
+                mem.put(0x9C00 + pos, (pos % 7 + 3) as u8);
+            }
+            for sprite in 0..40 {
+                mem.put(0xFE00 + sprite * 4, (10 + sprite * 3) as u8);
+                mem.put(0xFE01 + sprite * 4, (sprite * 17 % 176) as u8);
+                mem.put(0xFE02 + sprite * 4, (sprite % 16) as u8);
+                mem.put(0xFE03 + sprite * 4, ((sprite * 0x30) & 0xF0) as u8);
+            }
+            mem.put(0xFF47, 0xE4);
+            mem.put(0xFF48, 0xD2);
+            mem.put(0xFF49, 0x1B);
+            mem.put(0xFF42, 5);
+            mem.put(0xFF43, scx);
+            mem.put(0xFF4A, 40);
+            mem.put(0xFF4B, wx);
+            mem.put(0xFF40, lcdc);
+        }
+
+        let mut frames = 0;
+        while frames < 2 {
+            if gameboy.step() {
+                frames += 1;
+            }
+        }
+
+        gameboy.get_framebuffer().to_vec()
+    }
+
+    #[test]
+    fn pixel_fifo_matches_scanline() {
+        // without mid-line register changes both renderers draw the same frame
+        for &scx in [0u8, 3, 13].iter() {
+            for &wx in [3u8, 50, 200].iter() {
+                for &lcdc in [0xB3u8, 0xF7, 0x93].iter() {
+                    let scanline = render_frame(GBRenderer::Scanline, scx, wx, lcdc);
+                    assert!(scanline.contains(&0x00) && scanline.contains(&0xFF));
+                    assert!(scanline == render_frame(GBRenderer::PixelFifo, scx, wx, lcdc),
+                        "scx {} wx {} lcdc 0x{:02X}", scx, wx, lcdc);
+                }
+            }
+        }
     }
 
 }
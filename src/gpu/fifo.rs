@@ -0,0 +1,450 @@
+use std::collections::VecDeque;
+
+use cpu::GBCpu;
+use super::{SCREEN_WIDTH, LCDC_BG_ENABLE, LCDC_OBJ_ENABLE, LCDC_OBJ_SIZE, LCDC_BG_TILE_MAP,
+    LCDC_TILE_DATA, LCDC_WINDOW_ENABLE, LCDC_WINDOW_TILE_MAP, OBJ_BG_PRIORITY,
+    OBJ_X_FLIP, OBJ_PALETTE, GBLineSprite, sprite_row};
+
+// Pixel FIFO renderer: draws the line one pixel per cycle during the VRAM mode, so its length
+// depends on the fine scroll, the window and the sprites, and register writes in the middle
+// of the line take effect on the next pixels.
+// References:
+// - https://gbdev.io/pandocs/pixel_fifo.html
+// - http://gbdev.gg8.se/wiki/articles/Video_Display#Mode_3_Length
+
+// the first tile of the line is fetched twice, the first fetch is thrown away
+const STARTUP_CYCLES: usize = 6;
+
+// cycles to fetch a sprite, once the background fetcher is done with its tile
+const SPRITE_FETCH_CYCLES: usize = 6;
+
+#[derive(PartialEq)]
+enum GBFetcherStep {
+    Tile,
+    DataLow,
+    DataHigh,
+    Push,
+}
+
+#[derive(Clone, Copy)]
+struct GBObjPixel {
+    color: u8,
+    palette: usize, // OBP0 or OBP1 register
+    bg_priority: bool,
+}
+
+pub struct GBPixelFifo {
+
+    line: usize,
+    window_line: usize,
+    window_triggered: bool,
+    bg_fifo: VecDeque<u8>,
+    obj_fifo: VecDeque<GBObjPixel>,
+    // background/window fetcher. Each step takes 2 cycles, except the push which waits for an empty fifo
+    step: GBFetcherStep,
+    step_cycles: usize,
+    fetch_x: usize, // tile column
+    tile: u8,
+    tile_low: u8,
+    tile_high: u8,
+    // next column of the screen
+    x: usize,
+    // SCX % 8 pixels are dropped at the beginning of the line
+    discard: usize,
+    startup: usize,
+    // fetching the window instead of the background
+    window: bool,
+    // sprites of the line sorted by priority and if they were already fetched
+    sprites: Vec<(GBLineSprite, bool)>,
+    // sprite being fetched (index in sprites) and the cycles spent on it
+    sprite_fetch: Option<(usize, usize)>,
+
+}
+
+impl GBPixelFifo {
+
+    pub fn new() -> GBPixelFifo {
+        GBPixelFifo{
+            line: 0,
+            window_line: 0,
+            window_triggered: false,
+            bg_fifo: VecDeque::with_capacity(16),
+            obj_fifo: VecDeque::with_capacity(8),
+            step: GBFetcherStep::Tile,
+            step_cycles: 0,
+            fetch_x: 0,
+            tile: 0,
+            tile_low: 0,
+            tile_high: 0,
+            x: 0,
+            discard: 0,
+            startup: 0,
+            window: false,
+            sprites: Vec::with_capacity(10),
+            sprite_fetch: None,
+        }
+    }
+
+    // Called when entering the VRAM mode
+    pub fn start_line(&mut self, cpu: &GBCpu, line: usize, window_line: usize, window_triggered: bool, sprites: &[GBLineSprite]) {
+        self.line = line;
+        self.window_line = window_line;
+        self.window_triggered = window_triggered;
+        self.bg_fifo.clear();
+        self.obj_fifo.clear();
+        self.step = GBFetcherStep::Tile;
+        self.step_cycles = 0;
+        self.fetch_x = 0;
+        self.x = 0;
+        self.discard = (cpu.get_mem_ref().get_io(0xff43) & 0x7) as usize;
+        self.startup = STARTUP_CYCLES;
+        self.window = false;
+        self.sprites = sprites.iter().map(|&sprite| (sprite, false)).collect();
+        self.sprite_fetch = None;
+    }
+
+    // The whole line was pushed to the screen
+    pub fn is_done(&self) -> bool {
+        self.x == SCREEN_WIDTH
+    }
+
+    // The window was drawn in this line, so its line counter must advance
+    pub fn is_window_drawn(&self) -> bool {
+        self.window
+    }
+
+    // Runs a single cycle. Returns the pixel pushed to the screen, if any: (x, palette, color)
+    pub fn tick(&mut self, cpu: &GBCpu) -> Option<(usize, u8, u8)> {
+        if self.is_done() {
+            return None;
+        }
+
+        if self.startup > 0 {
+            self.startup -= 1;
+            return None;
+        }
+
+        let mem = cpu.get_mem_ref();
+        let lcdc = mem.get_io(0xff40);
+
+        // reaching WX - 7 restarts the fetcher on the window tiles
+        if self.window_starts(cpu, lcdc) {
+            let wx = mem.get_io(0xff4b) as usize;
+
+            self.window = true;
+            self.bg_fifo.clear();
+            self.step = GBFetcherStep::Tile;
+            self.step_cycles = 0;
+            self.fetch_x = 0;
+            // with WX < 7 the first 7 - WX pixels of the window are cut off
+            self.discard = 7usize.saturating_sub(wx);
+            // the fetcher starts on the window tiles in this same cycle
+        }
+
+        // a sprite starts at this column: the pixels stop until it's fetched
+        if self.sprite_fetch.is_none() && lcdc & LCDC_OBJ_ENABLE != 0 && self.discard == 0 {
+            let x = self.x + 8; // the sprite X is the screen X + 8
+            self.sprite_fetch = self.sprites.iter()
+                .position(|&(sprite, fetched)| !fetched && sprite.x <= x)
+                .map(|index| (index, 0));
+        }
+
+        if let Some((index, cycles)) = self.sprite_fetch {
+            // the background fetcher has to finish its current tile first
+            let bg_ready = !self.bg_fifo.is_empty()
+                && (self.step == GBFetcherStep::Push || (self.step == GBFetcherStep::Tile && self.step_cycles == 0));
+
+            if !bg_ready {
+                self.step_fetcher(cpu, lcdc);
+            } else if cycles + 1 < SPRITE_FETCH_CYCLES {
+                self.sprite_fetch = Some((index, cycles + 1));
+            } else {
+                self.fetch_sprite(cpu, lcdc, index);
+                self.sprite_fetch = None;
+            }
+
+            return None;
+        }
+
+        self.step_fetcher(cpu, lcdc);
+
+        let bg_color = self.bg_fifo.pop_front()?;
+        let obj_pixel = self.obj_fifo.pop_front();
+
+        if self.discard > 0 {
+            self.discard -= 1;
+            return None;
+        }
+
+        // with the background disabled it's just white (color 0)
+        let bg_color = if lcdc & LCDC_BG_ENABLE != 0 { bg_color } else { 0 };
+
+        // the palettes are read as the pixels are pushed, so mid line changes show up
+        let mut pixel = (self.x, mem.get_io(0xff47), bg_color);
+        if let Some(obj) = obj_pixel {
+            // color 0 is transparent and the background priority only hides the sprite behind the colors 1-3
+            if obj.color != 0 && lcdc & LCDC_OBJ_ENABLE != 0 && !(obj.bg_priority && bg_color != 0) {
+                pixel = (self.x, mem.get_io(obj.palette), obj.color);
+            }
+        }
+
+        self.x += 1;
+        Some(pixel)
+    }
+
+    fn window_starts(&self, cpu: &GBCpu, lcdc: u8) -> bool {
+        if self.window || !self.window_triggered || lcdc & LCDC_WINDOW_ENABLE == 0 {
+            return false;
+        }
+
+        // WX > 166 is off screen
+        let wx = cpu.get_mem_ref().get_io(0xff4b) as usize;
+        wx <= 166 && self.x + 7 >= wx
+    }
+
+    // Background/window fetcher, one cycle
+    fn step_fetcher(&mut self, cpu: &GBCpu, lcdc: u8) {
+        let mem = cpu.get_mem_ref();
+
+        if self.step == GBFetcherStep::Push {
+            // the 8 pixels only go in when the fifo is empty
+            if self.bg_fifo.is_empty() {
+                for bit in (0..8).rev() {
+                    let color = (((self.tile_high >> bit) & 0x1) << 1) | ((self.tile_low >> bit) & 0x1);
+                    self.bg_fifo.push_back(color);
+                }
+                self.fetch_x += 1;
+                self.step = GBFetcherStep::Tile;
+            }
+            return;
+        }
+
+        self.step_cycles += 1;
+        if self.step_cycles < 2 {
+            return;
+        }
+        self.step_cycles = 0;
+
+        // row of the tile being fetched
+        let y = if self.window {
+            self.window_line
+        } else {
+            (mem.get_io(0xff42) as usize + self.line) & 0xff
+        };
+
+        match self.step {
+            GBFetcherStep::Tile => {
+                let addr = if self.window {
+                    let map = if lcdc & LCDC_WINDOW_TILE_MAP != 0 { 0x9c00 } else { 0x9800 };
+                    map + (y / 8) * 32 + (self.fetch_x & 0x1f)
+                } else {
+                    let map = if lcdc & LCDC_BG_TILE_MAP != 0 { 0x9c00 } else { 0x9800 };
+                    let scx = mem.get_io(0xff43) as usize;
+                    map + (y / 8) * 32 + ((scx / 8 + self.fetch_x) & 0x1f)
+                };
+                self.tile = mem.get_vram(addr);
+                self.step = GBFetcherStep::DataLow;
+            },
+            GBFetcherStep::DataLow => {
+                self.tile_low = mem.get_vram(self.tile_row_addr(lcdc, y));
+                self.step = GBFetcherStep::DataHigh;
+            },
+            GBFetcherStep::DataHigh => {
+                self.tile_high = mem.get_vram(self.tile_row_addr(lcdc, y) + 1);
+                self.step = GBFetcherStep::Push;
+            },
+            GBFetcherStep::Push => {},
+        }
+    }
+
+    fn tile_row_addr(&self, lcdc: u8, y: usize) -> usize {
+        let tile_addr = if lcdc & LCDC_TILE_DATA != 0 {
+            0x8000 + (self.tile as usize) * 16
+        } else {
+            (0x9000 + (self.tile as i8 as isize) * 16) as usize
+        };
+
+        tile_addr + (y % 8) * 2
+    }
+
+    // Mixes the sprite pixels into the sprite fifo. Pixels already there have priority
+    // (smaller X or earlier in OAM), unless they are transparent
+    fn fetch_sprite(&mut self, cpu: &GBCpu, lcdc: u8, index: usize) {
+        let mem = cpu.get_mem_ref();
+        let (GBLineSprite{ oam, y, x }, _) = self.sprites[index];
+        self.sprites[index].1 = true;
+
+        let attributes = mem.get_oam(oam + 3);
+
+        let height = if lcdc & LCDC_OBJ_SIZE != 0 { 16 } else { 8 };
+        let tile = if height == 16 { mem.get_oam(oam + 2) & 0xfe } else { mem.get_oam(oam + 2) };
+
+        // the sprite size may have changed since the scan
+        let row = match sprite_row(self.line, y, height, attributes) {
+            Some(row) => row,
+            None => return,
+        };
+
+        let row_addr = 0x8000 + (tile as usize) * 16 + row * 2;
+        let low = mem.get_vram(row_addr);
+        let high = mem.get_vram(row_addr + 1);
+
+        let palette = if attributes & OBJ_PALETTE != 0 { 0xff49 } else { 0xff48 };
+
+        // columns already behind the current x (sprites partially out on the left)
+        let skip = (self.x + 8).saturating_sub(x);
+
+        for col in skip..8 {
+            let bit = if attributes & OBJ_X_FLIP != 0 { col } else { 7 - col };
+            let pixel = GBObjPixel{
+                color: (((high >> bit) & 0x1) << 1) | ((low >> bit) & 0x1),
+                palette,
+                bg_priority: attributes & OBJ_BG_PRIORITY != 0,
+            };
+
+            let pos = col - skip;
+            if pos < self.obj_fifo.len() {
+                if self.obj_fifo[pos].color == 0 {
+                    self.obj_fifo[pos] = pixel;
+                }
+            } else {
+                self.obj_fifo.push_back(pixel);
+            }
+        }
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+
+    use cpu::GBCpu;
+    use mem::GBMem;
+    use super::GBPixelFifo;
+    use gpu::GBLineSprite;
+
+    fn test_cpu(lcdc: u8, scx: u8) -> GBCpu {
+        let mut cpu = GBCpu::new(GBMem::new());
+        {
+            let mem = cpu.get_mem_mut();
+            mem.put(0xFF40, lcdc);
+            mem.put(0xFF43, scx);
+            // every sprite out of the screen
+            for sprite in 0..40 {
+                mem.put(0xFE00 + sprite * 4, 0);
+            }
+        }
+        cpu
+    }
+
+    fn set_sprite(cpu: &mut GBCpu, sprite: usize, y: u8, x: u8) {
+        cpu.get_mem_mut().put(0xFE00 + sprite * 4, y);
+        cpu.get_mem_mut().put(0xFE00 + sprite * 4 + 1, x);
+    }
+
+    // Sprites selected by the OAM scan, with the position in OAM
+    fn line_sprites(cpu: &GBCpu, sprites: &[usize]) -> Vec<GBLineSprite> {
+        let mem = cpu.get_mem_ref();
+        sprites.iter().map(|&sprite| {
+            let oam = 0xFE00 + sprite * 4;
+            GBLineSprite{ oam, y: mem.get_oam(oam) as usize, x: mem.get_oam(oam + 1) as usize }
+        }).collect()
+    }
+
+    // Cycles of the VRAM mode for line 0
+    fn mode3_length(cpu: &GBCpu, window_triggered: bool, sprites: &[usize]) -> usize {
+        let mut fifo = GBPixelFifo::new();
+        fifo.start_line(cpu, 0, 0, window_triggered, &line_sprites(cpu, sprites));
+
+        let mut cycles = 0;
+        while !fifo.is_done() {
+            fifo.tick(cpu);
+            cycles += 1;
+        }
+        cycles
+    }
+
+    #[test]
+    fn mode3_fine_scroll_penalty() {
+        for &scx in [0u8, 1, 5, 7, 8, 13].iter() {
+            let cpu = test_cpu(0x91, scx);
+            assert_eq!(mode3_length(&cpu, false, &[]), 172 + (scx & 0x7) as usize);
+        }
+    }
+
+    #[test]
+    fn mode3_window_penalty() {
+        let mut cpu = test_cpu(0xB1, 0);
+        cpu.get_mem_mut().put(0xFF4B, 87);
+        assert_eq!(mode3_length(&cpu, true, &[]), 172 + 6);
+
+        // not triggered yet (LY didn't match WY)
+        assert_eq!(mode3_length(&cpu, false, &[]), 172);
+    }
+
+    #[test]
+    fn mode3_sprite_penalty() {
+        let mut cpu = test_cpu(0x93, 0);
+
+        // 6 to 11 cycles per sprite, depending on how far the fetcher is from the end of its tile
+        for &x in [8u8, 40, 43, 47, 100].iter() {
+            set_sprite(&mut cpu, 0, 16, x);
+            let length = mode3_length(&cpu, false, &[0]);
+            assert!((172 + 6..=172 + 11).contains(&length), "x {}: {}", x, length);
+        }
+
+        // ignored with the sprites disabled
+        cpu.get_mem_mut().put(0xFF40, 0x91);
+        assert_eq!(mode3_length(&cpu, false, &[0]), 172);
+
+        // 10 sprites in the same column
+        cpu.get_mem_mut().put(0xFF40, 0x93);
+        for sprite in 0..10 {
+            set_sprite(&mut cpu, sprite, 16, 40);
+        }
+        let sprites = (0..10).collect::<Vec<usize>>();
+        assert!(mode3_length(&cpu, false, &sprites) >= 172 + 60);
+    }
+
+    #[test]
+    fn sprite_moved_during_the_line() {
+        let mut cpu = test_cpu(0x93, 0);
+        {
+            let mem = cpu.get_mem_mut();
+            mem.put(0xFF47, 0xE4);
+            mem.put(0xFF48, 0x1B);
+            // tile 1 is all color 3, the background (tile 0) all color 0
+            for pos in 0x8010..0x8020 {
+                mem.put(pos, 0xFF);
+            }
+            mem.put(0xFE02, 1);
+        }
+        set_sprite(&mut cpu, 0, 16, 40);
+
+        let mut fifo = GBPixelFifo::new();
+        fifo.start_line(&cpu, 0, 0, false, &line_sprites(&cpu, &[0]));
+
+        let mut pixels = vec!();
+        let mut cycles = 0;
+        while !fifo.is_done() {
+            if let Some(pixel) = fifo.tick(&cpu) {
+                pixels.push(pixel);
+            }
+            cycles += 1;
+
+            // moved out of the line after the OAM scan, before it's fetched
+            if cycles == 20 {
+                set_sprite(&mut cpu, 0, 100, 120);
+            }
+        }
+
+        // it's still drawn at the position latched by the scan, and fetching it stalls the fifo
+        assert!((172 + 6..=172 + 11).contains(&cycles), "{}", cycles);
+        assert_eq!(pixels.len(), 160);
+        for (x, &pixel) in pixels.iter().enumerate() {
+            let expected = if (32..40).contains(&x) { (x, 0x1B, 3) } else { (x, 0xE4, 0) };
+            assert_eq!(pixel, expected);
+        }
+    }
+
+}
@@ -0,0 +1,561 @@
+use std::fmt;
+
+// Typed representation of the LR35902 instruction set.
+// Each opcode is decoded once into an `Instruction`, which is used both by the cpu executor
+// and for disassembly (`Display`).
+// References:
+// - http://www.pastraiser.com/cpu/gameboy/gameboy_opcodes.html
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Reg8 {
+    A, B, C, D, E, H, L,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Reg16 {
+    AF, BC, DE, HL, SP,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Condition {
+    Always,
+    NotZero, // NZ
+    Zero, // Z
+    NotCarry, // NC
+    Carry, // C
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Operand {
+    Reg8(Reg8),
+    Reg16(Reg16),
+    Indirect(Reg16), // (BC), (DE), (HL)
+    IndirectHLInc, // (HL+)
+    IndirectHLDec, // (HL-)
+    IndirectC, // (C) => 0xFF00 + C
+    D8(u8),
+    D16(u16),
+    R8(i8),
+    Address(u16), // (a16)
+    HighAddress(u8), // (a8) => 0xFF00 + a8
+    SPOffset(i8), // SP+r8
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Instruction {
+    Nop,
+    Stop,
+    Halt,
+    Di,
+    Ei,
+    Ld(Operand, Operand),
+    Inc(Operand),
+    Dec(Operand),
+    Add(Operand, Operand),
+    Adc(Operand),
+    Sub(Operand),
+    Sbc(Operand),
+    And(Operand),
+    Xor(Operand),
+    Or(Operand),
+    Cp(Operand),
+    Rlca,
+    Rrca,
+    Rla,
+    Rra,
+    Daa,
+    Cpl,
+    Scf,
+    Ccf,
+    Jr(Condition, i8),
+    Jp(Condition, u16),
+    JpHL,
+    Call(Condition, u16),
+    Ret(Condition),
+    Reti,
+    Rst(u16),
+    Push(Reg16),
+    Pop(Reg16),
+    // CB prefixed
+    Rlc(Operand),
+    Rrc(Operand),
+    Rl(Operand),
+    Rr(Operand),
+    Sla(Operand),
+    Sra(Operand),
+    Swap(Operand),
+    Srl(Operand),
+    Bit(u8, Operand),
+    Res(u8, Operand),
+    Set(u8, Operand),
+    Invalid(u8),
+}
+
+pub struct GBDecoded {
+    pub opcode: u16, // CB prefixed opcodes are 0xcbXX
+    pub instruction: Instruction,
+    pub length: u16, // in bytes, including the opcode and the prefix
+}
+
+impl Operand {
+
+    // number of immediate bytes that follow the opcode
+    fn size(&self) -> u16 {
+        match *self {
+            Operand::D8(_) | Operand::R8(_) | Operand::HighAddress(_) | Operand::SPOffset(_) => 1,
+            Operand::D16(_) | Operand::Address(_) => 2,
+            _ => 0,
+        }
+    }
+
+}
+
+impl Instruction {
+
+    fn size(&self) -> u16 {
+        match *self {
+            Instruction::Stop | Instruction::Jr(..) => 2,
+            Instruction::Jp(..) | Instruction::Call(..) => 3,
+            Instruction::Ld(dst, src) | Instruction::Add(dst, src) => 1 + dst.size() + src.size(),
+            Instruction::Adc(op) | Instruction::Sub(op) | Instruction::Sbc(op) | Instruction::And(op)
+            | Instruction::Xor(op) | Instruction::Or(op) | Instruction::Cp(op) => 1 + op.size(),
+            _ => 1,
+        }
+    }
+
+}
+
+// Decodes the instruction at `pc`. `read` gives the byte at an address
+pub fn decode<F>(read: F, pc: u16) -> GBDecoded where F: Fn(u16) -> u8 {
+
+    use self::Instruction::*;
+    use self::Operand::*;
+    use self::Reg8::*;
+    use self::Reg16::*;
+    use self::Condition::*;
+
+    let opcode = read(pc);
+
+    // immediate data, only meaningful for the ops that take it
+    let d8 = read(pc.wrapping_add(1));
+    let d16 = (d8 as u16) | ((read(pc.wrapping_add(2)) as u16) << 8);
+    let r8 = d8 as i8;
+
+    let instruction = match opcode {
+        0x00 => Nop,
+        0x01 => Ld(Reg16(BC), D16(d16)),
+        0x02 => Ld(Indirect(BC), Reg8(A)),
+        0x03 => Inc(Reg16(BC)),
+        0x04 => Inc(Reg8(B)),
+        0x05 => Dec(Reg8(B)),
+        0x06 => Ld(Reg8(B), D8(d8)),
+        0x07 => Rlca,
+        0x08 => Ld(Address(d16), Reg16(SP)),
+        0x09 => Add(Reg16(HL), Reg16(BC)),
+        0x0a => Ld(Reg8(A), Indirect(BC)),
+        0x0b => Dec(Reg16(BC)),
+        0x0c => Inc(Reg8(C)),
+        0x0d => Dec(Reg8(C)),
+        0x0e => Ld(Reg8(C), D8(d8)),
+        0x0f => Rrca,
+        0x10 => Stop,
+        0x11 => Ld(Reg16(DE), D16(d16)),
+        0x12 => Ld(Indirect(DE), Reg8(A)),
+        0x13 => Inc(Reg16(DE)),
+        0x14 => Inc(Reg8(D)),
+        0x15 => Dec(Reg8(D)),
+        0x16 => Ld(Reg8(D), D8(d8)),
+        0x17 => Rla,
+        0x18 => Jr(Always, r8),
+        0x19 => Add(Reg16(HL), Reg16(DE)),
+        0x1a => Ld(Reg8(A), Indirect(DE)),
+        0x1b => Dec(Reg16(DE)),
+        0x1c => Inc(Reg8(E)),
+        0x1d => Dec(Reg8(E)),
+        0x1e => Ld(Reg8(E), D8(d8)),
+        0x1f => Rra,
+        0x20 => Jr(NotZero, r8),
+        0x21 => Ld(Reg16(HL), D16(d16)),
+        0x22 => Ld(IndirectHLInc, Reg8(A)),
+        0x23 => Inc(Reg16(HL)),
+        0x24 => Inc(Reg8(H)),
+        0x25 => Dec(Reg8(H)),
+        0x26 => Ld(Reg8(H), D8(d8)),
+        0x27 => Daa,
+        0x28 => Jr(Zero, r8),
+        0x29 => Add(Reg16(HL), Reg16(HL)),
+        0x2a => Ld(Reg8(A), IndirectHLInc),
+        0x2b => Dec(Reg16(HL)),
+        0x2c => Inc(Reg8(L)),
+        0x2d => Dec(Reg8(L)),
+        0x2e => Ld(Reg8(L), D8(d8)),
+        0x2f => Cpl,
+        0x30 => Jr(NotCarry, r8),
+        0x31 => Ld(Reg16(SP), D16(d16)),
+        0x32 => Ld(IndirectHLDec, Reg8(A)),
+        0x33 => Inc(Reg16(SP)),
+        0x34 => Inc(Indirect(HL)),
+        0x35 => Dec(Indirect(HL)),
+        0x36 => Ld(Indirect(HL), D8(d8)),
+        0x37 => Scf,
+        0x38 => Jr(Carry, r8),
+        0x39 => Add(Reg16(HL), Reg16(SP)),
+        0x3a => Ld(Reg8(A), IndirectHLDec),
+        0x3b => Dec(Reg16(SP)),
+        0x3c => Inc(Reg8(A)),
+        0x3d => Dec(Reg8(A)),
+        0x3e => Ld(Reg8(A), D8(d8)),
+        0x3f => Ccf,
+        0x40 => Ld(Reg8(B), Reg8(B)),
+        0x41 => Ld(Reg8(B), Reg8(C)),
+        0x42 => Ld(Reg8(B), Reg8(D)),
+        0x43 => Ld(Reg8(B), Reg8(E)),
+        0x44 => Ld(Reg8(B), Reg8(H)),
+        0x45 => Ld(Reg8(B), Reg8(L)),
+        0x46 => Ld(Reg8(B), Indirect(HL)),
+        0x47 => Ld(Reg8(B), Reg8(A)),
+        0x48 => Ld(Reg8(C), Reg8(B)),
+        0x49 => Ld(Reg8(C), Reg8(C)),
+        0x4a => Ld(Reg8(C), Reg8(D)),
+        0x4b => Ld(Reg8(C), Reg8(E)),
+        0x4c => Ld(Reg8(C), Reg8(H)),
+        0x4d => Ld(Reg8(C), Reg8(L)),
+        0x4e => Ld(Reg8(C), Indirect(HL)),
+        0x4f => Ld(Reg8(C), Reg8(A)),
+        0x50 => Ld(Reg8(D), Reg8(B)),
+        0x51 => Ld(Reg8(D), Reg8(C)),
+        0x52 => Ld(Reg8(D), Reg8(D)),
+        0x53 => Ld(Reg8(D), Reg8(E)),
+        0x54 => Ld(Reg8(D), Reg8(H)),
+        0x55 => Ld(Reg8(D), Reg8(L)),
+        0x56 => Ld(Reg8(D), Indirect(HL)),
+        0x57 => Ld(Reg8(D), Reg8(A)),
+        0x58 => Ld(Reg8(E), Reg8(B)),
+        0x59 => Ld(Reg8(E), Reg8(C)),
+        0x5a => Ld(Reg8(E), Reg8(D)),
+        0x5b => Ld(Reg8(E), Reg8(E)),
+        0x5c => Ld(Reg8(E), Reg8(H)),
+        0x5d => Ld(Reg8(E), Reg8(L)),
+        0x5e => Ld(Reg8(E), Indirect(HL)),
+        0x5f => Ld(Reg8(E), Reg8(A)),
+        0x60 => Ld(Reg8(H), Reg8(B)),
+        0x61 => Ld(Reg8(H), Reg8(C)),
+        0x62 => Ld(Reg8(H), Reg8(D)),
+        0x63 => Ld(Reg8(H), Reg8(E)),
+        0x64 => Ld(Reg8(H), Reg8(H)),
+        0x65 => Ld(Reg8(H), Reg8(L)),
+        0x66 => Ld(Reg8(H), Indirect(HL)),
+        0x67 => Ld(Reg8(H), Reg8(A)),
+        0x68 => Ld(Reg8(L), Reg8(B)),
+        0x69 => Ld(Reg8(L), Reg8(C)),
+        0x6a => Ld(Reg8(L), Reg8(D)),
+        0x6b => Ld(Reg8(L), Reg8(E)),
+        0x6c => Ld(Reg8(L), Reg8(H)),
+        0x6d => Ld(Reg8(L), Reg8(L)),
+        0x6e => Ld(Reg8(L), Indirect(HL)),
+        0x6f => Ld(Reg8(L), Reg8(A)),
+        0x70 => Ld(Indirect(HL), Reg8(B)),
+        0x71 => Ld(Indirect(HL), Reg8(C)),
+        0x72 => Ld(Indirect(HL), Reg8(D)),
+        0x73 => Ld(Indirect(HL), Reg8(E)),
+        0x74 => Ld(Indirect(HL), Reg8(H)),
+        0x75 => Ld(Indirect(HL), Reg8(L)),
+        0x76 => Halt,
+        0x77 => Ld(Indirect(HL), Reg8(A)),
+        0x78 => Ld(Reg8(A), Reg8(B)),
+        0x79 => Ld(Reg8(A), Reg8(C)),
+        0x7a => Ld(Reg8(A), Reg8(D)),
+        0x7b => Ld(Reg8(A), Reg8(E)),
+        0x7c => Ld(Reg8(A), Reg8(H)),
+        0x7d => Ld(Reg8(A), Reg8(L)),
+        0x7e => Ld(Reg8(A), Indirect(HL)),
+        0x7f => Ld(Reg8(A), Reg8(A)),
+        0x80 => Add(Reg8(A), Reg8(B)),
+        0x81 => Add(Reg8(A), Reg8(C)),
+        0x82 => Add(Reg8(A), Reg8(D)),
+        0x83 => Add(Reg8(A), Reg8(E)),
+        0x84 => Add(Reg8(A), Reg8(H)),
+        0x85 => Add(Reg8(A), Reg8(L)),
+        0x86 => Add(Reg8(A), Indirect(HL)),
+        0x87 => Add(Reg8(A), Reg8(A)),
+        0x88 => Adc(Reg8(B)),
+        0x89 => Adc(Reg8(C)),
+        0x8a => Adc(Reg8(D)),
+        0x8b => Adc(Reg8(E)),
+        0x8c => Adc(Reg8(H)),
+        0x8d => Adc(Reg8(L)),
+        0x8e => Adc(Indirect(HL)),
+        0x8f => Adc(Reg8(A)),
+        0x90 => Sub(Reg8(B)),
+        0x91 => Sub(Reg8(C)),
+        0x92 => Sub(Reg8(D)),
+        0x93 => Sub(Reg8(E)),
+        0x94 => Sub(Reg8(H)),
+        0x95 => Sub(Reg8(L)),
+        0x96 => Sub(Indirect(HL)),
+        0x97 => Sub(Reg8(A)),
+        0x98 => Sbc(Reg8(B)),
+        0x99 => Sbc(Reg8(C)),
+        0x9a => Sbc(Reg8(D)),
+        0x9b => Sbc(Reg8(E)),
+        0x9c => Sbc(Reg8(H)),
+        0x9d => Sbc(Reg8(L)),
+        0x9e => Sbc(Indirect(HL)),
+        0x9f => Sbc(Reg8(A)),
+        0xa0 => And(Reg8(B)),
+        0xa1 => And(Reg8(C)),
+        0xa2 => And(Reg8(D)),
+        0xa3 => And(Reg8(E)),
+        0xa4 => And(Reg8(H)),
+        0xa5 => And(Reg8(L)),
+        0xa6 => And(Indirect(HL)),
+        0xa7 => And(Reg8(A)),
+        0xa8 => Xor(Reg8(B)),
+        0xa9 => Xor(Reg8(C)),
+        0xaa => Xor(Reg8(D)),
+        0xab => Xor(Reg8(E)),
+        0xac => Xor(Reg8(H)),
+        0xad => Xor(Reg8(L)),
+        0xae => Xor(Indirect(HL)),
+        0xaf => Xor(Reg8(A)),
+        0xb0 => Or(Reg8(B)),
+        0xb1 => Or(Reg8(C)),
+        0xb2 => Or(Reg8(D)),
+        0xb3 => Or(Reg8(E)),
+        0xb4 => Or(Reg8(H)),
+        0xb5 => Or(Reg8(L)),
+        0xb6 => Or(Indirect(HL)),
+        0xb7 => Or(Reg8(A)),
+        0xb8 => Cp(Reg8(B)),
+        0xb9 => Cp(Reg8(C)),
+        0xba => Cp(Reg8(D)),
+        0xbb => Cp(Reg8(E)),
+        0xbc => Cp(Reg8(H)),
+        0xbd => Cp(Reg8(L)),
+        0xbe => Cp(Indirect(HL)),
+        0xbf => Cp(Reg8(A)),
+        0xc0 => Ret(NotZero),
+        0xc1 => Pop(BC),
+        0xc2 => Jp(NotZero, d16),
+        0xc3 => Jp(Always, d16),
+        0xc4 => Call(NotZero, d16),
+        0xc5 => Push(BC),
+        0xc6 => Add(Reg8(A), D8(d8)),
+        0xc7 => Rst(0x00),
+        0xc8 => Ret(Zero),
+        0xc9 => Ret(Always),
+        0xca => Jp(Zero, d16),
+        0xcb => return decode_cb(read, pc),
+        0xcc => Call(Zero, d16),
+        0xcd => Call(Always, d16),
+        0xce => Adc(D8(d8)),
+        0xcf => Rst(0x08),
+        0xd0 => Ret(NotCarry),
+        0xd1 => Pop(DE),
+        0xd2 => Jp(NotCarry, d16),
+        0xd3 => Invalid(opcode),
+        0xd4 => Call(NotCarry, d16),
+        0xd5 => Push(DE),
+        0xd6 => Sub(D8(d8)),
+        0xd7 => Rst(0x10),
+        0xd8 => Ret(Carry),
+        0xd9 => Reti,
+        0xda => Jp(Carry, d16),
+        0xdb => Invalid(opcode),
+        0xdc => Call(Carry, d16),
+        0xdd => Invalid(opcode),
+        0xde => Sbc(D8(d8)),
+        0xdf => Rst(0x18),
+        0xe0 => Ld(HighAddress(d8), Reg8(A)),
+        0xe1 => Pop(HL),
+        0xe2 => Ld(IndirectC, Reg8(A)),
+        0xe3 => Invalid(opcode),
+        0xe4 => Invalid(opcode),
+        0xe5 => Push(HL),
+        0xe6 => And(D8(d8)),
+        0xe7 => Rst(0x20),
+        0xe8 => Add(Reg16(SP), R8(r8)),
+        0xe9 => JpHL,
+        0xea => Ld(Address(d16), Reg8(A)),
+        0xeb => Invalid(opcode),
+        0xec => Invalid(opcode),
+        0xed => Invalid(opcode),
+        0xee => Xor(D8(d8)),
+        0xef => Rst(0x28),
+        0xf0 => Ld(Reg8(A), HighAddress(d8)),
+        0xf1 => Pop(AF),
+        0xf2 => Ld(Reg8(A), IndirectC),
+        0xf3 => Di,
+        0xf4 => Invalid(opcode),
+        0xf5 => Push(AF),
+        0xf6 => Or(D8(d8)),
+        0xf7 => Rst(0x30),
+        0xf8 => Ld(Reg16(HL), SPOffset(r8)),
+        0xf9 => Ld(Reg16(SP), Reg16(HL)),
+        0xfa => Ld(Reg8(A), Address(d16)),
+        0xfb => Ei,
+        0xfc => Invalid(opcode),
+        0xfd => Invalid(opcode),
+        0xfe => Cp(D8(d8)),
+        0xff => Rst(0x38),
+    };
+
+    GBDecoded{
+        opcode: opcode as u16,
+        length: instruction.size(),
+        instruction,
+    }
+
+}
+
+// The CB page is regular:
+// bits 7-6 select the group (rotate/shift, BIT, RES, SET), bits 5-3 the op or the bit
+// and bits 2-0 the target register
+const CB_TARGETS: [Operand; 8] = [
+    Operand::Reg8(Reg8::B),
+    Operand::Reg8(Reg8::C),
+    Operand::Reg8(Reg8::D),
+    Operand::Reg8(Reg8::E),
+    Operand::Reg8(Reg8::H),
+    Operand::Reg8(Reg8::L),
+    Operand::Indirect(Reg16::HL),
+    Operand::Reg8(Reg8::A),
+];
+
+fn decode_cb<F>(read: F, pc: u16) -> GBDecoded where F: Fn(u16) -> u8 {
+
+    use self::Instruction::*;
+
+    let opcode = read(pc.wrapping_add(1));
+    let target = CB_TARGETS[(opcode & 0x07) as usize];
+    let y = (opcode >> 3) & 0x07;
+
+    let instruction = match opcode >> 6 {
+        0x0 => match y {
+            0x0 => Rlc(target),
+            0x1 => Rrc(target),
+            0x2 => Rl(target),
+            0x3 => Rr(target),
+            0x4 => Sla(target),
+            0x5 => Sra(target),
+            0x6 => Swap(target),
+            _ => Srl(target),
+        },
+        0x1 => Bit(y, target),
+        0x2 => Res(y, target),
+        _ => Set(y, target),
+    };
+
+    GBDecoded{
+        opcode: 0xcb00 | (opcode as u16),
+        instruction,
+        length: 2,
+    }
+
+}
+
+impl fmt::Display for Reg8 {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(self, f)
+    }
+}
+
+impl fmt::Display for Reg16 {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(self, f)
+    }
+}
+
+impl fmt::Display for Condition {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match *self {
+            Condition::Always => "",
+            Condition::NotZero => "NZ",
+            Condition::Zero => "Z",
+            Condition::NotCarry => "NC",
+            Condition::Carry => "C",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl fmt::Display for Operand {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Operand::Reg8(r) => write!(f, "{}", r),
+            Operand::Reg16(r) => write!(f, "{}", r),
+            Operand::Indirect(r) => write!(f, "({})", r),
+            Operand::IndirectHLInc => write!(f, "(HL+)"),
+            Operand::IndirectHLDec => write!(f, "(HL-)"),
+            Operand::IndirectC => write!(f, "(C)"),
+            Operand::D8(v) => write!(f, "${:02X}", v),
+            Operand::D16(v) => write!(f, "${:04X}", v),
+            Operand::R8(v) => write!(f, "{}", v),
+            Operand::Address(v) => write!(f, "(${:04X})", v),
+            Operand::HighAddress(v) => write!(f, "($FF{:02X})", v),
+            Operand::SPOffset(v) => write!(f, "SP{:+}", v),
+        }
+    }
+}
+
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use self::Instruction::*;
+        use self::Condition::Always;
+
+        match *self {
+            Nop => write!(f, "NOP"),
+            Stop => write!(f, "STOP"),
+            Halt => write!(f, "HALT"),
+            Di => write!(f, "DI"),
+            Ei => write!(f, "EI"),
+            Ld(dst @ Operand::HighAddress(_), src) | Ld(dst, src @ Operand::HighAddress(_)) => {
+                write!(f, "LDH {},{}", dst, src)
+            },
+            Ld(dst, src) => write!(f, "LD {},{}", dst, src),
+            Inc(op) => write!(f, "INC {}", op),
+            Dec(op) => write!(f, "DEC {}", op),
+            Add(dst, src) => write!(f, "ADD {},{}", dst, src),
+            Adc(op) => write!(f, "ADC A,{}", op),
+            Sub(op) => write!(f, "SUB {}", op),
+            Sbc(op) => write!(f, "SBC A,{}", op),
+            And(op) => write!(f, "AND {}", op),
+            Xor(op) => write!(f, "XOR {}", op),
+            Or(op) => write!(f, "OR {}", op),
+            Cp(op) => write!(f, "CP {}", op),
+            Rlca => write!(f, "RLCA"),
+            Rrca => write!(f, "RRCA"),
+            Rla => write!(f, "RLA"),
+            Rra => write!(f, "RRA"),
+            Daa => write!(f, "DAA"),
+            Cpl => write!(f, "CPL"),
+            Scf => write!(f, "SCF"),
+            Ccf => write!(f, "CCF"),
+            Jr(Always, offset) => write!(f, "JR {}", offset),
+            Jr(cond, offset) => write!(f, "JR {},{}", cond, offset),
+            Jp(Always, addr) => write!(f, "JP ${:04X}", addr),
+            Jp(cond, addr) => write!(f, "JP {},${:04X}", cond, addr),
+            JpHL => write!(f, "JP (HL)"),
+            Call(Always, addr) => write!(f, "CALL ${:04X}", addr),
+            Call(cond, addr) => write!(f, "CALL {},${:04X}", cond, addr),
+            Ret(Always) => write!(f, "RET"),
+            Ret(cond) => write!(f, "RET {}", cond),
+            Reti => write!(f, "RETI"),
+            Rst(addr) => write!(f, "RST {:02X}H", addr),
+            Push(r) => write!(f, "PUSH {}", r),
+            Pop(r) => write!(f, "POP {}", r),
+            Rlc(op) => write!(f, "RLC {}", op),
+            Rrc(op) => write!(f, "RRC {}", op),
+            Rl(op) => write!(f, "RL {}", op),
+            Rr(op) => write!(f, "RR {}", op),
+            Sla(op) => write!(f, "SLA {}", op),
+            Sra(op) => write!(f, "SRA {}", op),
+            Swap(op) => write!(f, "SWAP {}", op),
+            Srl(op) => write!(f, "SRL {}", op),
+            Bit(b, op) => write!(f, "BIT {},{}", b, op),
+            Res(b, op) => write!(f, "RES {},{}", b, op),
+            Set(b, op) => write!(f, "SET {},{}", b, op),
+            Invalid(opcode) => write!(f, "DB ${:02X}", opcode),
+        }
+    }
+}
@@ -0,0 +1,179 @@
+// References:
+// - http://gbdev.gg8.se/wiki/articles/Joypad_Input
+
+// The 8 buttons of the DMG
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum GBButton {
+    Right,
+    Left,
+    Up,
+    Down,
+    A,
+    B,
+    Select,
+    Start,
+}
+
+// P1 select bits. They are active low
+const P1_SELECT_DIRECTIONS: u8 = 0x10;
+const P1_SELECT_ACTIONS: u8 = 0x20;
+
+pub struct GBJoypad {
+
+    pressed: u8, // one bit per button, in the GBButton order
+    select: u8, // P1 bits 4-5
+    interrupt_requested: bool,
+
+}
+
+impl GBJoypad {
+
+    pub fn new() -> GBJoypad {
+        GBJoypad{
+            pressed: 0x0,
+            select: 0x0,
+            interrupt_requested: false,
+        }
+    }
+
+    pub fn set_button(&mut self, button: GBButton, pressed: bool) {
+        let lines = self.lines();
+
+        let mask = 0x1 << (button as u8);
+        if pressed {
+            self.pressed |= mask;
+        } else {
+            self.pressed &= !mask;
+        }
+
+        self.check_interrupt(lines);
+    }
+
+    pub fn is_pressed(&self, button: GBButton) -> bool {
+        self.pressed & (0x1 << (button as u8)) != 0x0
+    }
+
+    // Returns true once for each high to low transition of the P1 lines (interrupt bit 4)
+    pub fn take_interrupt(&mut self) -> bool {
+        let requested = self.interrupt_requested;
+        self.interrupt_requested = false;
+        requested
+    }
+
+    // FF00. The unused bits 6-7 read 1
+    pub fn read(&self) -> u8 {
+        0xC0 | self.select | self.lines()
+    }
+
+    // FF00. Only the select bits can be written
+    pub fn write(&mut self, byte: u8) {
+        let lines = self.lines();
+        self.select = byte & (P1_SELECT_DIRECTIONS | P1_SELECT_ACTIONS);
+        self.check_interrupt(lines);
+    }
+
+    // P10-P13, 0 means pressed. With both groups selected the lines are and'ed
+    fn lines(&self) -> u8 {
+        let mut lines = 0x0F;
+
+        if self.select & P1_SELECT_DIRECTIONS == 0 {
+            lines &= !(self.pressed & 0x0F);
+        }
+        if self.select & P1_SELECT_ACTIONS == 0 {
+            lines &= !(self.pressed >> 4);
+        }
+
+        lines
+    }
+
+    fn check_interrupt(&mut self, prev_lines: u8) {
+        // any line going from high to low
+        if prev_lines & !self.lines() != 0 {
+            self.interrupt_requested = true;
+        }
+    }
+
+}
+
+impl Default for GBJoypad {
+
+    fn default() -> GBJoypad {
+        GBJoypad::new()
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::{GBJoypad, GBButton};
+
+    #[test]
+    fn select_bits_pick_the_group() {
+        let mut joypad = GBJoypad::new();
+        joypad.set_button(GBButton::Down, true);
+        joypad.set_button(GBButton::A, true);
+
+        // nothing selected: all the lines are high
+        joypad.write(0x30);
+        assert_eq!(joypad.read(), 0xFF);
+
+        // P14 low: directions. Down is P13
+        joypad.write(0x20);
+        assert_eq!(joypad.read(), 0xE7);
+
+        // P15 low: actions. A is P10
+        joypad.write(0x10);
+        assert_eq!(joypad.read(), 0xDE);
+
+        // both groups selected
+        joypad.write(0x00);
+        assert_eq!(joypad.read(), 0xC6);
+
+        // the lower bits are read only
+        joypad.write(0x3F);
+        assert_eq!(joypad.read(), 0xFF);
+    }
+
+    #[test]
+    fn interrupt_on_high_to_low() {
+        let mut joypad = GBJoypad::new();
+        joypad.write(0x20);
+
+        // not selected, the line doesn't change
+        joypad.set_button(GBButton::Start, true);
+        assert!(!joypad.take_interrupt());
+
+        joypad.set_button(GBButton::Up, true);
+        assert!(joypad.take_interrupt());
+        assert!(!joypad.take_interrupt());
+
+        // releasing is a low to high transition
+        joypad.set_button(GBButton::Up, false);
+        assert!(!joypad.take_interrupt());
+
+        // selecting the group with Start already pressed pulls P13 low
+        joypad.write(0x10);
+        assert!(joypad.take_interrupt());
+    }
+
+    #[test]
+    fn repeated_press_does_not_trigger_again() {
+        let mut joypad = GBJoypad::new();
+        joypad.write(0x10);
+
+        joypad.set_button(GBButton::B, true);
+        assert!(joypad.take_interrupt());
+
+        // key repeat from the frontend, the line is already low
+        joypad.set_button(GBButton::B, true);
+        assert!(!joypad.take_interrupt());
+
+        // Left shares P11 with B, which is already low
+        joypad.write(0x00);
+        joypad.set_button(GBButton::Left, true);
+        assert!(!joypad.take_interrupt());
+    }
+
+}
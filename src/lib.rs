@@ -0,0 +1,18 @@
+#[macro_use]
+extern crate log;
+
+pub mod regset;
+pub mod instruction;
+pub mod cpu;
+pub mod mem;
+pub mod cartridge;
+pub mod mbc;
+pub mod timer;
+pub mod gpu;
+pub mod joypad;
+pub mod gameboy;
+
+pub use gameboy::GameBoy;
+pub use joypad::GBButton;
+pub use gpu::GBRenderer;
+pub use cartridge::{Cartridge, CartridgeError};
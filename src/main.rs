@@ -1,109 +1,123 @@
-#[macro_use] extern crate log;
-extern crate  bit_vec;
+extern crate rust_gameboy;
 extern crate sdl2;
 
-mod regset;
-mod cpu;
-mod mem;
-mod gpu;
 mod sdl_display;
 
 use std::io::prelude::*;
 use std::fs::File;
-use std::io;
+use std::env;
+use std::process;
+use std::path::Path;
+use std::thread;
+use std::time::Duration;
 
-use cpu::GBCpu;
-use mem::GBMem;
+use rust_gameboy::{GameBoy, GBRenderer, Cartridge};
 use sdl_display::{SDLDisplay, SDLDisplayEvent};
-use gpu::GBGpu;
 
-fn main() {
+// Flush the save file every ~5 seconds (60 frames per second)
+const SAVE_FLUSH_FRAMES: usize = 300;
 
-    let mut mem = GBMem::new();
+fn main() {
 
-    {
-        let rom_file = File::open("etc/boot.bin").unwrap();
+    let mut rom_path = None;
+    let mut no_boot = false;
+    let mut renderer = GBRenderer::Scanline;
+
+    for arg in env::args().skip(1) {
+        match arg.as_str() {
+            // start from the cartridge with the post boot state, without running the boot rom
+            "--no-boot" => no_boot = true,
+            // cycle accurate pixel fifo instead of drawing whole lines
+            "--fifo" => renderer = GBRenderer::PixelFifo,
+            _ => rom_path = Some(arg),
+        }
+    }
 
-        for (pos, byte) in rom_file.bytes().enumerate() {
+    let rom_path = match rom_path {
+        Some(path) => path,
+        None => {
+            println!("Usage: rust-gameboy [--no-boot] [--fifo] <rom file>");
+            process::exit(1);
+        },
+    };
+
+    let cartridge = match Cartridge::from_file(&rom_path) {
+        Ok(cartridge) => cartridge,
+        Err(err) => {
+            println!("{}: {}", rom_path, err);
+            process::exit(1);
+        },
+    };
+
+    println!("{:?}", cartridge.get_header());
+    if !cartridge.is_global_checksum_valid() {
+        println!("{}: global checksum mismatch", rom_path);
+    }
 
-            mem.put(pos, byte.unwrap());
+    let mut gameboy = GameBoy::new();
+    gameboy.load_cartridge(cartridge);
+    gameboy.set_renderer(renderer);
 
-        }
+    // battery backed ram (and rtc) from the previous session
+    let save_path = Path::new(&rom_path).with_extension("sav");
+    if let Err(err) = gameboy.load_save_file(&save_path) {
+        println!("{}: {}", save_path.display(), err);
     }
 
-    let mut cpu = GBCpu::new(mem);
+    if no_boot {
+        gameboy.skip_boot();
+    } else {
+        let mut rom_file = File::open("etc/boot.bin").unwrap();
+        let mut rom = vec!();
+        rom_file.read_to_end(&mut rom).unwrap();
 
-    let mut display = SDLDisplay::new(600, 800, "rust-gameboy".to_string());
+        gameboy.load_boot_rom(&rom);
+    }
 
-    let mut gpu = GBGpu::new();
+    let mut display = SDLDisplay::new(600, 800, "rust-gameboy".to_string());
 
-    let mut count = 0;
+    let mut frames = 0;
 
     'main_loop: loop {
-    // for _ in 0..24577+5+12+39+39 {
-    // for _ in 0..28817 {
 
-        println!("Cycles: {}", cpu.get_last_op_cycles());
-        println!("SP: 0x{:04X}", cpu.get_sp());
-        println!("PC: 0x{:04X}", cpu.get_pc());
-        println!("OP: 0x{:04X}", cpu.get_mem_ref().get(cpu.get_pc() as usize));
-        println!("{:?}", cpu.get_regset_ref());
+        let frame_done = gameboy.step();
 
-        cpu.step();
-        gpu.step(&mut cpu, &mut display);
-        display.step();
+        // nothing is clocked while the cpu is stopped (STOP), so no frame is completed. The events
+        // still have to be read: a button press is what wakes it up
+        let stopped = gameboy.get_cpu_ref().get_last_op_cycles() == 0;
 
-        println!("-------------", );
-
-        count += 1;
-        if cpu.get_pc() == 0x40 {
-            break 'main_loop;
-        }
+        if frame_done {
+            display.update(gameboy.get_framebuffer());
 
-        for event in display.get_events().iter() {
-            match event {
-                &SDLDisplayEvent::Quit => break 'main_loop,
+            // don't lose the progress if the emulator is killed
+            frames += 1;
+            if frames % SAVE_FLUSH_FRAMES == 0 {
+                if let Err(err) = gameboy.flush_save_file(&save_path) {
+                    println!("{}: {}", save_path.display(), err);
+                }
             }
         }
 
-    }
-
-
-    'read_loop: loop {
-
-        println!("Count: {:?}", count);
-        println!("Cycles: {}", cpu.get_last_op_cycles());
-        println!("SP: 0x{:04X}", cpu.get_sp());
-        println!("PC: 0x{:04X}", cpu.get_pc());
-        println!("OP: 0x{:04X}", cpu.get_mem_ref().get(cpu.get_pc() as usize));
-        println!("{:?}", cpu.get_regset_ref());
-        cpu.get_mem_ref().dump("tmp/mem.bin");
-
-        cpu.step();
-        gpu.step(&mut cpu, &mut display);
-
-        println!("-------------", );
+        if frame_done || stopped {
+            display.step();
 
-        count += 1;
-
-        let stdin = io::stdin();
-        let line = stdin.lock().lines().next().unwrap().unwrap();
-
-        if line == "q" {
-            break 'read_loop;
+            for event in display.get_events().iter() {
+                match event {
+                    &SDLDisplayEvent::Quit => break 'main_loop,
+                    &SDLDisplayEvent::Button{ button, pressed } => gameboy.set_input(button, pressed),
+                }
+            }
         }
 
-        for event in display.get_events().iter() {
-            match event {
-                &SDLDisplayEvent::Quit => break 'read_loop,
-            }
+        // don't spin while waiting for a button
+        if stopped {
+            thread::sleep(Duration::from_millis(16));
         }
 
     }
 
-    println!("SP: 0x{:04X}", cpu.get_sp());
-    println!("PC: 0x{:04X}", cpu.get_pc());
-    println!("OP: 0x{:04X}", cpu.get_mem_ref().get(cpu.get_pc() as usize));
-    println!("{:?}", cpu.get_regset_ref());
+    if let Err(err) = gameboy.flush_save_file(&save_path) {
+        println!("{}: {}", save_path.display(), err);
+    }
 
 }
@@ -0,0 +1,142 @@
+use mbc::GBMbc;
+
+// Up to 2MB of rom (125 usable banks) and 32KB of ram
+pub struct GBMbc1 {
+
+    ram_enabled: bool,
+    // 2000-3FFF. Lower 5 bits of the rom bank
+    bank1: usize,
+    // 4000-5FFF. Ram bank or upper 2 bits of the rom bank
+    bank2: usize,
+    // 6000-7FFF. In mode 1 bank2 also applies to 0000-3FFF and to the ram
+    mode: u8,
+
+}
+
+impl GBMbc1 {
+
+    pub fn new() -> GBMbc1 {
+        GBMbc1{
+            ram_enabled: false,
+            bank1: 1,
+            bank2: 0,
+            mode: 0,
+        }
+    }
+
+    fn ram_offset(&self, ram: &[u8], pos: usize) -> usize {
+        let bank = if self.mode == 1 { self.bank2 } else { 0 };
+        // 2KB ram carts mirror it through the whole area
+        (bank * 0x2000 + (pos - 0xA000)) % ram.len()
+    }
+
+}
+
+impl Default for GBMbc1 {
+
+    fn default() -> GBMbc1 {
+        GBMbc1::new()
+    }
+
+}
+
+impl GBMbc for GBMbc1 {
+
+    fn read_rom(&self, rom: &[u8], pos: usize) -> u8 {
+        let bank = match pos {
+            0x0000..=0x3FFF if self.mode == 1 => self.bank2 << 5,
+            0x0000..=0x3FFF => 0,
+            _ => (self.bank2 << 5) | self.bank1,
+        };
+
+        // the bank number wraps around the rom size (always a power of 2)
+        let offset = (bank * 0x4000 + (pos & 0x3FFF)) & (rom.len() - 1);
+        rom[offset]
+    }
+
+    fn write_rom(&mut self, pos: usize, byte: u8) {
+        match pos {
+            0x0000..=0x1FFF => self.ram_enabled = byte & 0x0F == 0x0A,
+            0x2000..=0x3FFF => {
+                // bank 0 can't be selected here, it's translated to 1. Only the 5 bits
+                // are checked, so 0x20, 0x40 and 0x60 end up as 0x21, 0x41 and 0x61
+                self.bank1 = match byte & 0x1F {
+                    0 => 1,
+                    bank => bank as usize,
+                };
+            },
+            0x4000..=0x5FFF => self.bank2 = (byte & 0x03) as usize,
+            0x6000..=0x7FFF => self.mode = byte & 0x01,
+            _ => {},
+        }
+    }
+
+    fn read_ram(&self, ram: &[u8], pos: usize) -> u8 {
+        if !self.ram_enabled || ram.is_empty() {
+            return 0xFF;
+        }
+
+        ram[self.ram_offset(ram, pos)]
+    }
+
+    fn write_ram(&mut self, ram: &mut [u8], pos: usize, byte: u8) -> bool {
+        if !self.ram_enabled || ram.is_empty() {
+            return false;
+        }
+
+        let offset = self.ram_offset(ram, pos);
+        ram[offset] = byte;
+        true
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+
+    use mbc::GBMbc;
+    use super::GBMbc1;
+
+    // 2MB rom where the first byte of each bank is its number
+    fn banked_rom() -> Vec<u8> {
+        let mut rom = vec![0; 128 * 0x4000];
+        for bank in 0..128 {
+            rom[bank * 0x4000] = bank as u8;
+        }
+        rom
+    }
+
+    #[test]
+    fn bank_0_is_translated_to_1() {
+        let rom = banked_rom();
+        let mut mbc = GBMbc1::new();
+
+        mbc.write_rom(0x2000, 0x00);
+        assert_eq!(mbc.read_rom(&rom, 0x4000), 0x01);
+
+        // only the lower 5 bits are checked: 0x20 selects 0x21
+        mbc.write_rom(0x4000, 0x01);
+        mbc.write_rom(0x2000, 0x00);
+        assert_eq!(mbc.read_rom(&rom, 0x4000), 0x21);
+
+        mbc.write_rom(0x2000, 0x05);
+        assert_eq!(mbc.read_rom(&rom, 0x4000), 0x25);
+    }
+
+    #[test]
+    fn mode_1_banks_0000_3fff() {
+        let rom = banked_rom();
+        let mut mbc = GBMbc1::new();
+
+        mbc.write_rom(0x4000, 0x02);
+        assert_eq!(mbc.read_rom(&rom, 0x0000), 0x00);
+
+        mbc.write_rom(0x6000, 0x01);
+        assert_eq!(mbc.read_rom(&rom, 0x0000), 0x40);
+        assert_eq!(mbc.read_rom(&rom, 0x4000), 0x41);
+
+        mbc.write_rom(0x6000, 0x00);
+        assert_eq!(mbc.read_rom(&rom, 0x0000), 0x00);
+    }
+
+}
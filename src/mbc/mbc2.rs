@@ -0,0 +1,151 @@
+use mbc::GBMbc;
+
+// Size of the built-in ram: 512 x 4 bits (one nibble per byte)
+pub const MBC2_RAM_SIZE: usize = 512;
+
+// Up to 256KB of rom and a built-in 512x4 bits ram
+pub struct GBMbc2 {
+
+    ram_enabled: bool,
+    rom_bank: usize,
+
+}
+
+impl GBMbc2 {
+
+    pub fn new() -> GBMbc2 {
+        GBMbc2{
+            ram_enabled: false,
+            rom_bank: 1,
+        }
+    }
+
+}
+
+impl Default for GBMbc2 {
+
+    fn default() -> GBMbc2 {
+        GBMbc2::new()
+    }
+
+}
+
+impl GBMbc for GBMbc2 {
+
+    fn read_rom(&self, rom: &[u8], pos: usize) -> u8 {
+        let bank = match pos {
+            0x0000..=0x3FFF => 0,
+            _ => self.rom_bank,
+        };
+
+        let offset = (bank * 0x4000 + (pos & 0x3FFF)) & (rom.len() - 1);
+        rom[offset]
+    }
+
+    fn write_rom(&mut self, pos: usize, byte: u8) {
+        match pos {
+            // the address bit 8 selects the register: clear - ram enable, set - rom bank
+            0x0000..=0x3FFF if pos & 0x100 == 0 => self.ram_enabled = byte & 0x0F == 0x0A,
+            0x0000..=0x3FFF => {
+                self.rom_bank = match byte & 0x0F {
+                    0 => 1,
+                    bank => bank as usize,
+                };
+            },
+            _ => {},
+        }
+    }
+
+    fn read_ram(&self, ram: &[u8], pos: usize) -> u8 {
+        if !self.ram_enabled {
+            return 0xFF;
+        }
+
+        // only the lower nibble exists, the upper one reads as 1s.
+        // The 512 bytes are echoed through the whole A000-BFFF area
+        ram[(pos - 0xA000) & 0x1FF] | 0xF0
+    }
+
+    fn write_ram(&mut self, ram: &mut [u8], pos: usize, byte: u8) -> bool {
+        if !self.ram_enabled {
+            return false;
+        }
+
+        ram[(pos - 0xA000) & 0x1FF] = byte & 0x0F;
+        true
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+
+    use mbc::GBMbc;
+    use super::{GBMbc2, MBC2_RAM_SIZE};
+
+    // 256KB rom where the first byte of each bank is its number
+    fn banked_rom() -> Vec<u8> {
+        let mut rom = vec![0; 16 * 0x4000];
+        for bank in 0..16 {
+            rom[bank * 0x4000] = bank as u8;
+        }
+        rom
+    }
+
+    #[test]
+    fn address_bit_8_selects_the_register() {
+        let rom = banked_rom();
+        let mut ram = vec![0; MBC2_RAM_SIZE];
+        let mut mbc = GBMbc2::new();
+
+        // bit 8 clear: ram enable, the rom bank doesn't change
+        mbc.write_rom(0x0000, 0x0A);
+        assert_eq!(mbc.read_rom(&rom, 0x4000), 0x01);
+        assert!(mbc.write_ram(&mut ram, 0xA000, 0x05));
+
+        // bit 8 set: rom bank, the ram stays enabled
+        mbc.write_rom(0x2100, 0x03);
+        assert_eq!(mbc.read_rom(&rom, 0x4000), 0x03);
+        assert_eq!(mbc.read_ram(&ram, 0xA000), 0xF5);
+
+        // anywhere in 0000-3FFF, only the bit 8 matters
+        mbc.write_rom(0x3E00, 0x00);
+        assert_eq!(mbc.read_ram(&ram, 0xA000), 0xFF);
+        mbc.write_rom(0x0100, 0x07);
+        assert_eq!(mbc.read_rom(&rom, 0x4000), 0x07);
+    }
+
+    #[test]
+    fn ram_is_4_bits() {
+        let mut ram = vec![0; MBC2_RAM_SIZE];
+        let mut mbc = GBMbc2::new();
+
+        assert!(!mbc.write_ram(&mut ram, 0xA000, 0x12));
+
+        mbc.write_rom(0x0000, 0x0A);
+        assert!(mbc.write_ram(&mut ram, 0xA000, 0xAB));
+        assert_eq!(ram[0], 0x0B);
+        assert_eq!(mbc.read_ram(&ram, 0xA000), 0xFB);
+
+        // echoed every 512 bytes
+        assert_eq!(mbc.read_ram(&ram, 0xA200), 0xFB);
+        assert_eq!(mbc.read_ram(&ram, 0xBE00), 0xFB);
+    }
+
+    #[test]
+    fn bank_0_is_translated_to_1() {
+        let rom = banked_rom();
+        let mut mbc = GBMbc2::new();
+
+        mbc.write_rom(0x2100, 0x00);
+        assert_eq!(mbc.read_rom(&rom, 0x4000), 0x01);
+
+        // 4 bits: 0x10 is bank 0 as well
+        mbc.write_rom(0x2100, 0x10);
+        assert_eq!(mbc.read_rom(&rom, 0x4000), 0x01);
+
+        mbc.write_rom(0x2100, 0x1F);
+        assert_eq!(mbc.read_rom(&rom, 0x4000), 0x0F);
+    }
+
+}
@@ -0,0 +1,377 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use mbc::GBMbc;
+
+// Size of the rtc footer appended to the save ram. Same layout used by BGB and VBA-M:
+// current and latched registers (5 x u32 each) followed by the unix timestamp (u64),
+// all little endian. Older versions store a 32 bits timestamp (44 bytes)
+const RTC_STATE_SIZE: usize = 48;
+const RTC_STATE_SIZE_OLD: usize = 44;
+
+// DH register bits
+const RTC_DAY_HIGH: u8 = 0x01; // bit 8 of the day counter
+const RTC_HALT: u8 = 0x40;
+const RTC_DAY_CARRY: u8 = 0x80;
+
+fn unix_time() -> u64 {
+    match SystemTime::now().duration_since(UNIX_EPOCH) {
+        Ok(time) => time.as_secs(),
+        Err(_) => 0,
+    }
+}
+
+// Real time clock. It's driven by the host clock: the elapsed time is added
+// to the registers whenever they are latched or written
+#[derive(Clone, Copy)]
+struct GBRtc {
+    seconds: u8,
+    minutes: u8,
+    hours: u8,
+    days: u16, // 9 bits
+    halt: bool,
+    carry: bool,
+}
+
+impl GBRtc {
+
+    fn new() -> GBRtc {
+        GBRtc{ seconds: 0, minutes: 0, hours: 0, days: 0, halt: false, carry: false }
+    }
+
+    fn advance(&mut self, elapsed: u64) {
+        if self.halt || elapsed == 0 {
+            return;
+        }
+
+        let total = elapsed
+            + self.seconds as u64
+            + self.minutes as u64 * 60
+            + self.hours as u64 * 3600
+            + self.days as u64 * 86400;
+
+        self.seconds = (total % 60) as u8;
+        self.minutes = ((total / 60) % 60) as u8;
+        self.hours = ((total / 3600) % 24) as u8;
+
+        // the day counter overflow sets the carry, which stays until the game clears it
+        let days = total / 86400;
+        if days > 0x1FF {
+            self.carry = true;
+        }
+        self.days = (days & 0x1FF) as u16;
+    }
+
+    // 08-0C
+    fn get(&self, reg: u8) -> u8 {
+        match reg {
+            0x08 => self.seconds,
+            0x09 => self.minutes,
+            0x0A => self.hours,
+            0x0B => self.days as u8,
+            0x0C => {
+                let mut dh = (self.days >> 8) as u8 & RTC_DAY_HIGH;
+                if self.halt { dh |= RTC_HALT; }
+                if self.carry { dh |= RTC_DAY_CARRY; }
+                dh
+            },
+            _ => 0xFF,
+        }
+    }
+
+    fn put(&mut self, reg: u8, byte: u8) {
+        match reg {
+            0x08 => self.seconds = byte & 0x3F,
+            0x09 => self.minutes = byte & 0x3F,
+            0x0A => self.hours = byte & 0x1F,
+            0x0B => self.days = (self.days & 0x100) | byte as u16,
+            0x0C => {
+                self.days = (self.days & 0xFF) | (((byte & RTC_DAY_HIGH) as u16) << 8);
+                self.halt = byte & RTC_HALT != 0;
+                self.carry = byte & RTC_DAY_CARRY != 0;
+            },
+            _ => {},
+        }
+    }
+
+}
+
+// Up to 2MB of rom, 32KB of ram and an optional real time clock
+pub struct GBMbc3 {
+
+    has_rtc: bool,
+    // enables both the ram and the rtc registers
+    ram_enabled: bool,
+    rom_bank: usize,
+    // 4000-5FFF. 00-07 selects a ram bank, 08-0C an rtc register
+    ram_bank: u8,
+    rtc: GBRtc,
+    rtc_latched: GBRtc,
+    // host time of the last rtc update
+    rtc_timestamp: u64,
+    // 6000-7FFF. Writing 0x00 and then 0x01 latches the clock
+    latch_prev: u8,
+
+}
+
+impl GBMbc3 {
+
+    pub fn new(has_rtc: bool) -> GBMbc3 {
+        GBMbc3{
+            has_rtc,
+            ram_enabled: false,
+            rom_bank: 1,
+            ram_bank: 0,
+            rtc: GBRtc::new(),
+            rtc_latched: GBRtc::new(),
+            rtc_timestamp: unix_time(),
+            latch_prev: 0xFF,
+        }
+    }
+
+    // adds the host time elapsed since the last update
+    fn update_rtc(&mut self) {
+        let now = unix_time();
+        if now > self.rtc_timestamp {
+            self.rtc.advance(now - self.rtc_timestamp);
+        }
+        self.rtc_timestamp = now;
+    }
+
+    fn ram_offset(&self, ram: &[u8], pos: usize) -> usize {
+        (self.ram_bank as usize * 0x2000 + (pos - 0xA000)) % ram.len()
+    }
+
+}
+
+impl GBMbc for GBMbc3 {
+
+    fn read_rom(&self, rom: &[u8], pos: usize) -> u8 {
+        let bank = match pos {
+            0x0000..=0x3FFF => 0,
+            _ => self.rom_bank,
+        };
+
+        let offset = (bank * 0x4000 + (pos & 0x3FFF)) & (rom.len() - 1);
+        rom[offset]
+    }
+
+    fn write_rom(&mut self, pos: usize, byte: u8) {
+        match pos {
+            0x0000..=0x1FFF => self.ram_enabled = byte & 0x0F == 0x0A,
+            // 7 bits, bank 0 is translated to 1
+            0x2000..=0x3FFF => {
+                self.rom_bank = match byte & 0x7F {
+                    0 => 1,
+                    bank => bank as usize,
+                };
+            },
+            0x4000..=0x5FFF => self.ram_bank = byte,
+            0x6000..=0x7FFF => {
+                if self.has_rtc && self.latch_prev == 0x00 && byte == 0x01 {
+                    self.update_rtc();
+                    self.rtc_latched = self.rtc;
+                }
+                self.latch_prev = byte;
+            },
+            _ => {},
+        }
+    }
+
+    fn read_ram(&self, ram: &[u8], pos: usize) -> u8 {
+        if !self.ram_enabled {
+            return 0xFF;
+        }
+
+        match self.ram_bank {
+            0x00..=0x07 if !ram.is_empty() => ram[self.ram_offset(ram, pos)],
+            // the game reads the latched copy, so the value doesn't change while it's being read
+            0x08..=0x0C if self.has_rtc => self.rtc_latched.get(self.ram_bank),
+            _ => 0xFF,
+        }
+    }
+
+    fn write_ram(&mut self, ram: &mut [u8], pos: usize, byte: u8) -> bool {
+        if !self.ram_enabled {
+            return false;
+        }
+
+        match self.ram_bank {
+            0x00..=0x07 if !ram.is_empty() => {
+                let offset = self.ram_offset(ram, pos);
+                ram[offset] = byte;
+                true
+            },
+            0x08..=0x0C if self.has_rtc => {
+                // bring the clock up to date, so the write isn't overwritten by the elapsed time
+                self.update_rtc();
+                self.rtc.put(self.ram_bank, byte);
+                // writes are visible through the latched registers as well
+                self.rtc_latched.put(self.ram_bank, byte);
+                true
+            },
+            _ => false,
+        }
+    }
+
+    fn has_rtc(&self) -> bool {
+        self.has_rtc
+    }
+
+    fn save_state(&self) -> Vec<u8> {
+        if !self.has_rtc {
+            return vec!();
+        }
+
+        let mut state = Vec::with_capacity(RTC_STATE_SIZE);
+        for rtc in [self.rtc, self.rtc_latched].iter() {
+            for reg in 0x08..0x0D {
+                state.extend_from_slice(&(rtc.get(reg) as u32).to_le_bytes());
+            }
+        }
+        state.extend_from_slice(&self.rtc_timestamp.to_le_bytes());
+
+        state
+    }
+
+    fn load_state(&mut self, state: &[u8]) {
+        if !self.has_rtc || (state.len() != RTC_STATE_SIZE && state.len() != RTC_STATE_SIZE_OLD) {
+            return;
+        }
+
+        // only the lower byte of each register is used
+        for reg in 0x08..0x0D {
+            let index = (reg - 0x08) as usize;
+            self.rtc.put(reg, state[index * 4]);
+            self.rtc_latched.put(reg, state[20 + index * 4]);
+        }
+
+        let mut timestamp = [0u8; 8];
+        timestamp[..state.len() - 40].copy_from_slice(&state[40..]);
+        self.rtc_timestamp = u64::from_le_bytes(timestamp);
+
+        // the clock kept running while the emulator was closed
+        self.update_rtc();
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+
+    use mbc::GBMbc;
+    use super::{GBMbc3, GBRtc, unix_time};
+
+    // rtc enabled and halted, so the host clock doesn't change the registers during the test
+    fn halted_mbc() -> GBMbc3 {
+        let mut mbc = GBMbc3::new(true);
+        mbc.write_rom(0x0000, 0x0A);
+        mbc.write_rom(0x4000, 0x0C);
+        mbc.write_ram(&mut [], 0xA000, 0x40);
+        mbc
+    }
+
+    #[test]
+    fn latch_needs_00_then_01() {
+        let mut mbc = halted_mbc();
+        mbc.rtc.seconds = 30;
+
+        // the game reads the latched copy
+        mbc.write_rom(0x4000, 0x08);
+        assert_eq!(mbc.read_ram(&[], 0xA000), 0x00);
+
+        mbc.write_rom(0x6000, 0x01);
+        assert_eq!(mbc.read_ram(&[], 0xA000), 0x00);
+
+        mbc.write_rom(0x6000, 0x00);
+        mbc.write_rom(0x6000, 0x01);
+        assert_eq!(mbc.read_ram(&[], 0xA000), 30);
+
+        // writing 0x01 again doesn't latch
+        mbc.rtc.seconds = 45;
+        mbc.write_rom(0x6000, 0x01);
+        assert_eq!(mbc.read_ram(&[], 0xA000), 30);
+    }
+
+    #[test]
+    fn advance_rolls_over() {
+        let mut rtc = GBRtc::new();
+        rtc.advance(86400 + 3600 + 60 + 1);
+        assert_eq!((rtc.days, rtc.hours, rtc.minutes, rtc.seconds), (1, 1, 1, 1));
+        assert!(!rtc.carry);
+
+        // the day counter is 9 bits, the 9th one is in DH
+        rtc.put(0x0C, 0x01);
+        rtc.put(0x0B, 0xFF);
+        assert_eq!(rtc.days, 0x1FF);
+
+        // day 0x1FF 23:59:59 + 1s overflows and sets the carry
+        let mut rtc = GBRtc::new();
+        rtc.days = 0x1FF;
+        rtc.hours = 23;
+        rtc.minutes = 59;
+        rtc.seconds = 59;
+        rtc.advance(1);
+        assert_eq!((rtc.days, rtc.hours, rtc.minutes, rtc.seconds), (0, 0, 0, 0));
+        assert_eq!(rtc.get(0x0C), 0x80);
+
+        // the carry stays until it's cleared
+        rtc.advance(86400);
+        assert_eq!(rtc.get(0x0C), 0x80);
+        rtc.put(0x0C, 0x00);
+        assert_eq!(rtc.get(0x0C), 0x00);
+    }
+
+    #[test]
+    fn halt_stops_the_clock() {
+        let mut rtc = GBRtc::new();
+        rtc.put(0x0C, 0x40);
+        rtc.advance(3600);
+        assert_eq!((rtc.days, rtc.hours, rtc.minutes, rtc.seconds), (0, 0, 0, 0));
+        assert_eq!(rtc.get(0x0C), 0x40);
+
+        rtc.put(0x0C, 0x00);
+        rtc.advance(3600);
+        assert_eq!(rtc.hours, 1);
+    }
+
+    #[test]
+    fn state_roundtrip() {
+        let mut mbc = halted_mbc();
+        mbc.write_rom(0x4000, 0x08);
+        mbc.write_ram(&mut [], 0xA000, 12);
+        mbc.write_rom(0x4000, 0x0A);
+        mbc.write_ram(&mut [], 0xA000, 5);
+
+        let state = mbc.save_state();
+        assert_eq!(state.len(), 48);
+
+        let mut loaded = GBMbc3::new(true);
+        loaded.load_state(&state);
+        assert_eq!(loaded.rtc_latched.get(0x08), 12);
+        assert_eq!(loaded.rtc_latched.get(0x0A), 5);
+        assert_eq!(loaded.rtc_latched.get(0x0C), 0x40);
+        assert_eq!(loaded.rtc.get(0x08), 12);
+
+        // no clock, no state
+        assert!(GBMbc3::new(false).save_state().is_empty());
+    }
+
+    #[test]
+    fn old_state_has_a_32_bits_timestamp() {
+        // running clock at 00:00:00, saved an hour ago
+        let mut state = vec![0u8; 40];
+        state.extend_from_slice(&((unix_time() - 3600) as u32).to_le_bytes());
+        assert_eq!(state.len(), 44);
+
+        let mut mbc = GBMbc3::new(true);
+        mbc.load_state(&state);
+        assert_eq!(mbc.rtc.hours, 1);
+        assert_eq!(mbc.rtc.minutes, 0);
+
+        // anything else is ignored
+        let mut mbc = GBMbc3::new(true);
+        mbc.load_state(&state[..43]);
+        assert_eq!(mbc.rtc.hours, 0);
+    }
+
+}
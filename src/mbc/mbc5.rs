@@ -0,0 +1,171 @@
+use mbc::GBMbc;
+
+// Up to 8MB of rom (9 bits bank number) and 128KB of ram
+pub struct GBMbc5 {
+
+    has_rumble: bool,
+    ram_enabled: bool,
+    // 2000-2FFF lower 8 bits, 3000-3FFF bit 9. Unlike MBC1/3, bank 0 can be mapped to 4000-7FFF
+    rom_bank: usize,
+    ram_bank: usize,
+    // bit 3 of 4000-5FFF in rumble cartridges
+    rumble: bool,
+
+}
+
+impl GBMbc5 {
+
+    pub fn new(has_rumble: bool) -> GBMbc5 {
+        GBMbc5{
+            has_rumble,
+            ram_enabled: false,
+            rom_bank: 1,
+            ram_bank: 0,
+            rumble: false,
+        }
+    }
+
+    fn ram_offset(&self, ram: &[u8], pos: usize) -> usize {
+        (self.ram_bank * 0x2000 + (pos - 0xA000)) % ram.len()
+    }
+
+}
+
+impl GBMbc for GBMbc5 {
+
+    fn read_rom(&self, rom: &[u8], pos: usize) -> u8 {
+        let bank = match pos {
+            0x0000..=0x3FFF => 0,
+            _ => self.rom_bank,
+        };
+
+        let offset = (bank * 0x4000 + (pos & 0x3FFF)) & (rom.len() - 1);
+        rom[offset]
+    }
+
+    fn write_rom(&mut self, pos: usize, byte: u8) {
+        match pos {
+            0x0000..=0x1FFF => self.ram_enabled = byte == 0x0A,
+            0x2000..=0x2FFF => self.rom_bank = (self.rom_bank & 0x100) | byte as usize,
+            0x3000..=0x3FFF => self.rom_bank = (self.rom_bank & 0xFF) | (((byte & 0x01) as usize) << 8),
+            0x4000..=0x5FFF => {
+                // the rumble motor is wired to the bit 3, leaving 8 ram banks
+                if self.has_rumble {
+                    self.rumble = byte & 0x08 != 0;
+                    self.ram_bank = (byte & 0x07) as usize;
+                } else {
+                    self.ram_bank = (byte & 0x0F) as usize;
+                }
+            },
+            _ => {},
+        }
+    }
+
+    fn read_ram(&self, ram: &[u8], pos: usize) -> u8 {
+        if !self.ram_enabled || ram.is_empty() {
+            return 0xFF;
+        }
+
+        ram[self.ram_offset(ram, pos)]
+    }
+
+    fn write_ram(&mut self, ram: &mut [u8], pos: usize, byte: u8) -> bool {
+        if !self.ram_enabled || ram.is_empty() {
+            return false;
+        }
+
+        let offset = self.ram_offset(ram, pos);
+        ram[offset] = byte;
+        true
+    }
+
+    fn is_rumbling(&self) -> bool {
+        self.rumble
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+
+    use mbc::GBMbc;
+    use super::GBMbc5;
+
+    // 8MB rom where the first two bytes of each bank are its number
+    fn banked_rom() -> Vec<u8> {
+        let mut rom = vec![0; 512 * 0x4000];
+        for bank in 0..512 {
+            rom[bank * 0x4000] = bank as u8;
+            rom[bank * 0x4000 + 1] = (bank >> 8) as u8;
+        }
+        rom
+    }
+
+    fn rom_bank(mbc: &GBMbc5, rom: &[u8]) -> usize {
+        mbc.read_rom(rom, 0x4000) as usize | (mbc.read_rom(rom, 0x4001) as usize) << 8
+    }
+
+    #[test]
+    fn rom_bank_is_9_bits() {
+        let rom = banked_rom();
+        let mut mbc = GBMbc5::new(false);
+        assert_eq!(rom_bank(&mbc, &rom), 0x001);
+
+        mbc.write_rom(0x2000, 0x34);
+        mbc.write_rom(0x3000, 0x01);
+        assert_eq!(rom_bank(&mbc, &rom), 0x134);
+
+        // each half is kept when the other one changes
+        mbc.write_rom(0x2000, 0xFF);
+        assert_eq!(rom_bank(&mbc, &rom), 0x1FF);
+        mbc.write_rom(0x3000, 0x00);
+        assert_eq!(rom_bank(&mbc, &rom), 0x0FF);
+
+        // bank 0 isn't translated to 1
+        mbc.write_rom(0x2000, 0x00);
+        assert_eq!(rom_bank(&mbc, &rom), 0x000);
+    }
+
+    #[test]
+    fn ram_has_16_banks() {
+        let mut ram = vec![0; 16 * 0x2000];
+        let mut mbc = GBMbc5::new(false);
+        mbc.write_rom(0x0000, 0x0A);
+
+        for bank in 0..16 {
+            mbc.write_rom(0x4000, bank);
+            assert!(mbc.write_ram(&mut ram, 0xA000, bank + 1));
+        }
+
+        for bank in 0..16 {
+            assert_eq!(ram[bank * 0x2000], bank as u8 + 1);
+            mbc.write_rom(0x4000, bank as u8);
+            assert_eq!(mbc.read_ram(&ram, 0xA000), bank as u8 + 1);
+        }
+    }
+
+    #[test]
+    fn rumble_bit_is_not_a_ram_bank() {
+        let mut ram = vec![0; 16 * 0x2000];
+        let mut mbc = GBMbc5::new(true);
+        mbc.write_rom(0x0000, 0x0A);
+
+        mbc.write_rom(0x4000, 0x0B);
+        assert!(mbc.is_rumbling());
+        assert!(mbc.write_ram(&mut ram, 0xA000, 0x12));
+        assert_eq!(ram[3 * 0x2000], 0x12);
+
+        mbc.write_rom(0x4000, 0x03);
+        assert!(!mbc.is_rumbling());
+        assert_eq!(mbc.read_ram(&ram, 0xA000), 0x12);
+
+        // without a motor the bit 3 selects the bank
+        let mut mbc = GBMbc5::new(false);
+        mbc.write_rom(0x0000, 0x0A);
+        mbc.write_rom(0x4000, 0x0B);
+        assert!(!mbc.is_rumbling());
+        assert!(mbc.write_ram(&mut ram, 0xA000, 0x34));
+        assert_eq!(ram[11 * 0x2000], 0x34);
+    }
+
+}
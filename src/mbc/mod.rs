@@ -0,0 +1,77 @@
+// Memory bank controllers. They sit between the bus and the cartridge rom/ram,
+// translating the cpu addresses to the selected banks.
+// References:
+// - http://gbdev.gg8.se/wiki/articles/Memory_Bank_Controllers
+
+mod mbc1;
+mod mbc2;
+mod mbc3;
+mod mbc5;
+
+pub use self::mbc1::GBMbc1;
+pub use self::mbc2::{GBMbc2, MBC2_RAM_SIZE};
+pub use self::mbc3::GBMbc3;
+pub use self::mbc5::GBMbc5;
+
+pub trait GBMbc {
+    // 0000-7FFF
+    fn read_rom(&self, rom: &[u8], pos: usize) -> u8;
+    // 0000-7FFF. Writes to the rom area set the controller registers
+    fn write_rom(&mut self, pos: usize, byte: u8);
+    // A000-BFFF
+    fn read_ram(&self, ram: &[u8], pos: usize) -> u8;
+    // A000-BFFF. Returns true if something was stored (e.g. the ram is enabled)
+    fn write_ram(&mut self, ram: &mut [u8], pos: usize, byte: u8) -> bool;
+
+    // Extra battery backed state (e.g. the mbc3 clock). It's stored after the ram in the save file
+    fn save_state(&self) -> Vec<u8> {
+        vec!()
+    }
+
+    fn load_state(&mut self, _state: &[u8]) {
+    }
+
+    // The clock keeps changing without any ram write, so the save file is always flushed
+    fn has_rtc(&self) -> bool {
+        false
+    }
+
+    // Rumble motor state (MBC5 rumble cartridges)
+    fn is_rumbling(&self) -> bool {
+        false
+    }
+}
+
+// 32KB rom with up to 8KB of ram and no banking
+pub struct GBRomOnly;
+
+impl GBMbc for GBRomOnly {
+
+    fn read_rom(&self, rom: &[u8], pos: usize) -> u8 {
+        match rom.get(pos) {
+            Some(byte) => *byte,
+            None => 0xFF,
+        }
+    }
+
+    fn write_rom(&mut self, _pos: usize, _byte: u8) {
+    }
+
+    fn read_ram(&self, ram: &[u8], pos: usize) -> u8 {
+        match ram.get(pos - 0xA000) {
+            Some(byte) => *byte,
+            None => 0xFF,
+        }
+    }
+
+    fn write_ram(&mut self, ram: &mut [u8], pos: usize, byte: u8) -> bool {
+        match ram.get_mut(pos - 0xA000) {
+            Some(data) => {
+                *data = byte;
+                true
+            },
+            None => false,
+        }
+    }
+
+}
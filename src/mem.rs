@@ -1,30 +1,366 @@
-
 use std::io::prelude::*;
 use std::fs::File;
 
+use cartridge::Cartridge;
+use timer::GBTimer;
+use joypad::{GBJoypad, GBButton};
+
+// Memory map
+// 0000-00FF - boot rom, until it's unmapped by a write to FF50
+// 0000-7FFF - cartridge rom
+// 8000-9FFF - video ram
+// A000-BFFF - cartridge (external) ram
+// C000-DFFF - work ram
+// E000-FDFF - echo of C000-DDFF
+// FE00-FE9F - sprite attribute table (OAM)
+// FEA0-FEFF - not usable
+// FF00-FF7F - io registers
+// FF80-FFFE - high ram
+// FFFF      - interrupt enable register
 pub struct GBMem {
-    map: Vec<u8>,
+    boot_rom: Vec<u8>,
+    boot_rom_mapped: bool,
+    cartridge: Option<Cartridge>,
+    vram: Vec<u8>,
+    wram: Vec<u8>,
+    oam: Vec<u8>,
+    io: Vec<u8>,
+    hram: Vec<u8>,
+    ie: u8,
+    timer: GBTimer,
+    joypad: GBJoypad,
+    // OAM DMA: source address and the next byte to copy
+    dma_active: bool,
+    dma_source: usize,
+    dma_index: usize,
 }
 
 impl GBMem {
 
     pub fn new() -> GBMem {
         GBMem{
-            map: vec![0; 1024 * 64], // 64KB
+            boot_rom: vec!(),
+            boot_rom_mapped: false,
+            cartridge: None,
+            vram: vec![0; 0x2000], // 8KB
+            wram: vec![0; 0x2000], // 8KB
+            oam: vec![0; 0xA0],
+            io: vec![0; 0x80],
+            hram: vec![0; 0x7F],
+            ie: 0,
+            timer: GBTimer::new(),
+            joypad: GBJoypad::new(),
+            dma_active: false,
+            dma_source: 0,
+            dma_index: 0,
         }
     }
 
+    // Maps the boot rom over the beginning of the cartridge rom
+    pub fn load_boot_rom(&mut self, boot_rom: &[u8]) {
+        self.boot_rom = boot_rom.to_vec();
+        self.boot_rom_mapped = true;
+    }
+
+    pub fn load_cartridge(&mut self, cartridge: Cartridge) {
+        self.cartridge = Some(cartridge);
+    }
+
+    pub fn get_cartridge_ref(&self) -> Option<&Cartridge> {
+        self.cartridge.as_ref()
+    }
+
+    pub fn get_cartridge_mut(&mut self) -> Option<&mut Cartridge> {
+        self.cartridge.as_mut()
+    }
+
+    pub fn is_boot_rom_mapped(&self) -> bool {
+        self.boot_rom_mapped
+    }
+
+    // io registers as left by the boot rom
+    // References:
+    // - http://gbdev.gg8.se/wiki/articles/Power_Up_Sequence
+    pub fn skip_boot(&mut self) {
+        let registers = [
+            (0xFF0F, 0xE1), // IF
+            (0xFF10, 0x80), // NR10
+            (0xFF11, 0xBF), // NR11
+            (0xFF12, 0xF3), // NR12
+            (0xFF14, 0xBF), // NR14
+            (0xFF16, 0x3F), // NR21
+            (0xFF17, 0x00), // NR22
+            (0xFF19, 0xBF), // NR24
+            (0xFF1A, 0x7F), // NR30
+            (0xFF1B, 0xFF), // NR31
+            (0xFF1C, 0x9F), // NR32
+            (0xFF1E, 0xBF), // NR34
+            (0xFF20, 0xFF), // NR41
+            (0xFF21, 0x00), // NR42
+            (0xFF22, 0x00), // NR43
+            (0xFF23, 0xBF), // NR44
+            (0xFF24, 0x77), // NR50
+            (0xFF25, 0xF3), // NR51
+            (0xFF26, 0xF1), // NR52
+            (0xFF40, 0x91), // LCDC
+            (0xFF41, 0x85), // STAT
+            (0xFF42, 0x00), // SCY
+            (0xFF43, 0x00), // SCX
+            (0xFF45, 0x00), // LYC
+            (0xFF47, 0xFC), // BGP
+            (0xFF48, 0xFF), // OBP0
+            (0xFF49, 0xFF), // OBP1
+            (0xFF4A, 0x00), // WY
+            (0xFF4B, 0x00), // WX
+        ];
+
+        for &(pos, byte) in registers.iter() {
+            self.set_io(pos, byte);
+        }
+        self.ie = 0x00;
+
+        // DIV = 0xAB
+        self.timer.set_counter(0xABCC);
+
+        // the last thing the boot rom does is unmapping itself
+        self.set_io(0xFF50, 0x01);
+        self.boot_rom_mapped = false;
+    }
+
+    // Write from the cpu. Each region has its own semantics
     pub fn put(&mut self, pos: usize, byte: u8) {
-        self.map[pos] = byte;
+        // while the DMA is running the cpu can only reach HRAM
+        if self.dma_active && !(0xFF80..=0xFFFE).contains(&pos) {
+            return;
+        }
+
+        match pos {
+            // the cartridge decides what to do with rom writes (bank switching)
+            0x0000..=0x7FFF => if let Some(ref mut cartridge) = self.cartridge {
+                cartridge.write_rom(pos, byte);
+            },
+            0x8000..=0x9FFF => self.vram[pos - 0x8000] = byte,
+            0xA000..=0xBFFF => if let Some(ref mut cartridge) = self.cartridge {
+                cartridge.write_ram(pos, byte);
+            },
+            0xC000..=0xDFFF => self.wram[pos - 0xC000] = byte,
+            0xE000..=0xFDFF => self.wram[pos - 0xE000] = byte,
+            0xFE00..=0xFE9F => self.oam[pos - 0xFE00] = byte,
+            // writes to the unusable area are ignored
+            0xFEA0..=0xFEFF => {},
+            0xFF00..=0xFF7F => self.write_io(pos, byte),
+            0xFF80..=0xFFFE => self.hram[pos - 0xFF80] = byte,
+            0xFFFF => self.ie = byte,
+            _ => panic!("Write out of the memory map: 0x{:X}", pos),
+        }
     }
 
+    // Read from the cpu
     pub fn get(&self, pos: usize) -> u8 {
-        self.map[pos].clone()
+        if self.dma_active && !(0xFF80..=0xFFFE).contains(&pos) {
+            return 0xFF;
+        }
+
+        self.read(pos)
+    }
+
+    // Reads without the DMA restriction (used by the DMA itself)
+    fn read(&self, pos: usize) -> u8 {
+        match pos {
+            0x0000..=0x00FF if self.boot_rom_mapped && pos < self.boot_rom.len() => self.boot_rom[pos],
+            // without a cartridge the data lines are pulled up
+            0x0000..=0x7FFF => match self.cartridge {
+                Some(ref cartridge) => cartridge.read_rom(pos),
+                None => 0xFF,
+            },
+            0x8000..=0x9FFF => self.vram[pos - 0x8000],
+            0xA000..=0xBFFF => match self.cartridge {
+                Some(ref cartridge) => cartridge.read_ram(pos),
+                None => 0xFF,
+            },
+            0xC000..=0xDFFF => self.wram[pos - 0xC000],
+            0xE000..=0xFDFF => self.wram[pos - 0xE000],
+            0xFE00..=0xFE9F => self.oam[pos - 0xFE00],
+            0xFEA0..=0xFEFF => 0x00,
+            0xFF00..=0xFF7F => self.read_io(pos),
+            0xFF80..=0xFFFE => self.hram[pos - 0xFF80],
+            0xFFFF => self.ie,
+            _ => panic!("Read out of the memory map: 0x{:X}", pos),
+        }
     }
 
+    // Raw access to the io registers, used by the hardware itself (e.g. the lcd updating LY).
+    // It bypasses the cpu semantics like read only bits
+    pub fn get_io(&self, pos: usize) -> u8 {
+        self.io[pos - 0xFF00]
+    }
+
+    pub fn set_io(&mut self, pos: usize, byte: u8) {
+        self.io[pos - 0xFF00] = byte;
+    }
+
+    // IE, for the interrupt logic of the cpu (not restricted by the DMA)
+    pub fn get_ie(&self) -> u8 {
+        self.ie
+    }
+
+    pub fn set_ie(&mut self, byte: u8) {
+        self.ie = byte;
+    }
+
+    // STOP resets the divider. It doesn't go through the bus, which is blocked during DMA
+    pub fn reset_divider(&mut self) {
+        self.timer.write(0xFF04, 0);
+    }
+
+    // Clocks the timer and the DMA with the cycles of the last instruction
+    pub fn step(&mut self, cycles: usize) {
+        self.timer.step(cycles);
+
+        // timer overflow, interrupt bit 2
+        if self.timer.take_interrupt() {
+            let requests = self.get_io(0xFF0F);
+            self.set_io(0xFF0F, requests | 0x04);
+        }
+
+        self.step_dma(cycles);
+    }
+
+    // Button state from the frontend. A new press requests the joypad interrupt (bit 4),
+    // which also wakes the cpu from STOP
+    pub fn set_button(&mut self, button: GBButton, pressed: bool) {
+        self.joypad.set_button(button, pressed);
+        self.check_joypad_interrupt();
+    }
+
+    pub fn get_joypad_ref(&self) -> &GBJoypad {
+        &self.joypad
+    }
+
+    fn check_joypad_interrupt(&mut self) {
+        if self.joypad.take_interrupt() {
+            let requests = self.get_io(0xFF0F);
+            self.set_io(0xFF0F, requests | 0x10);
+        }
+    }
+
+    pub fn is_dma_active(&self) -> bool {
+        self.dma_active
+    }
+
+    // OAM DMA copies one byte every 4 cycles: 160 bytes in 640 cycles
+    fn step_dma(&mut self, cycles: usize) {
+        for _ in 0..(cycles / 4) {
+            if !self.dma_active {
+                break;
+            }
+
+            let byte = self.read(self.dma_source + self.dma_index);
+            self.oam[self.dma_index] = byte;
+
+            self.dma_index += 1;
+            if self.dma_index == self.oam.len() {
+                self.dma_active = false;
+            }
+        }
+    }
+
+    // Video memory as seen by the gpu, which isn't affected by the DMA
+    pub fn get_vram(&self, pos: usize) -> u8 {
+        self.vram[pos - 0x8000]
+    }
+
+    pub fn get_oam(&self, pos: usize) -> u8 {
+        self.oam[pos - 0xFE00]
+    }
+
+    // io register writes from the cpu. Registers with side effects are handled here
+    fn write_io(&mut self, pos: usize, byte: u8) {
+        match pos {
+            // P1: selects the buttons group
+            0xFF00 => {
+                self.joypad.write(byte);
+                self.check_joypad_interrupt();
+            },
+            // DIV, TIMA, TMA, TAC
+            0xFF04..=0xFF07 => self.timer.write(pos, byte),
+            // IF: only the lower 5 bits exist
+            0xFF0F => self.set_io(pos, byte & 0x1F),
+            // STAT: mode and coincidence bits (0-2) are read only
+            0xFF41 => {
+                let value = (self.get_io(pos) & 0x07) | (byte & 0x78);
+                self.set_io(pos, value);
+            },
+            // LY: read only
+            0xFF44 => {},
+            // DMA: starts copying XX00-XX9F to the OAM. Sources above DFFF read from the echo ram
+            0xFF46 => {
+                self.set_io(pos, byte);
+                self.dma_source = match (byte as usize) << 8 {
+                    source @ 0xE000..=0xFFFF => source - 0x2000,
+                    source => source,
+                };
+                self.dma_index = 0;
+                self.dma_active = true;
+            },
+            // BOOT: writing to it unmaps the boot rom. It can't be mapped again
+            0xFF50 => if byte != 0 {
+                self.boot_rom_mapped = false;
+            },
+            _ => self.set_io(pos, byte),
+        }
+    }
+
+    fn read_io(&self, pos: usize) -> u8 {
+        match pos {
+            0xFF00 => self.joypad.read(),
+            0xFF04..=0xFF07 => self.timer.read(pos),
+            // IF: the unused upper bits always read 1
+            0xFF0F => self.get_io(pos) | 0xE0,
+            // STAT: bit 7 always reads 1
+            0xFF41 => self.get_io(pos) | 0x80,
+            _ => self.get_io(pos),
+        }
+    }
+
+    // Dumps the memory as seen by the cpu
     pub fn dump(&self, filename: &str) {
+        let map: Vec<u8> = (0..0x10000).map(|pos| self.get(pos)).collect();
         let mut f = File::create(filename).unwrap();
-        f.write_all(&self.map).unwrap();
+        f.write_all(&map).unwrap();
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::GBMem;
+
+    #[test]
+    fn dma_restricts_the_cpu_to_hram() {
+        let mut mem = GBMem::new();
+        mem.put(0xC000, 0x12);
+        mem.put(0xFF80, 0x34);
+        mem.put(0xFFFF, 0x01);
+
+        mem.put(0xFF46, 0xC0);
+        assert!(mem.is_dma_active());
+
+        assert_eq!(mem.get(0xC000), 0xFF);
+        assert_eq!(mem.get(0xFF0F), 0xFF);
+        assert_eq!(mem.get(0xFFFF), 0xFF);
+        assert_eq!(mem.get(0xFF80), 0x34);
+
+        // the writes outside HRAM are ignored
+        mem.put(0xFFFF, 0x1F);
+        mem.put(0xFF81, 0x56);
+
+        mem.step(640);
+        assert!(!mem.is_dma_active());
+        assert_eq!(mem.get_oam(0xFE00), 0x12);
+        assert_eq!(mem.get(0xFFFF), 0x01);
+        assert_eq!(mem.get(0xFF81), 0x56);
     }
 
 }
@@ -1,138 +1,175 @@
-use std::collections::HashMap;
 use std::fmt;
 
-enum RegisterWrapper {
-    Pointer{ origin: String, position: usize },
-    Raw(u16),
-}
+use instruction::Reg8;
+
+// Flag register (F) bits
+const FLAG_Z: u8 = 0x80; // zero
+const FLAG_N: u8 = 0x40; // subtract
+const FLAG_H: u8 = 0x20; // half carry
+const FLAG_C: u8 = 0x10; // carry
 
 pub struct GBRegisterSet {
 
-    registers: HashMap<String, RegisterWrapper>,
+    a: u8,
+    f: u8, // only the upper nibble exists, the lower one always reads 0
+    b: u8,
+    c: u8,
+    d: u8,
+    e: u8,
+    h: u8,
+    l: u8,
 
 }
 
 impl GBRegisterSet {
 
-    pub fn new(set: Vec<&str>) -> GBRegisterSet {
-        let mut map = HashMap::new();
+    pub fn new() -> GBRegisterSet {
+        GBRegisterSet{ a: 0, f: 0, b: 0, c: 0, d: 0, e: 0, h: 0, l: 0 }
+    }
+
+    pub fn get(&self, reg: Reg8) -> u8 {
+        match reg {
+            Reg8::A => self.a,
+            Reg8::B => self.b,
+            Reg8::C => self.c,
+            Reg8::D => self.d,
+            Reg8::E => self.e,
+            Reg8::H => self.h,
+            Reg8::L => self.l,
+        }
+    }
+
+    pub fn put(&mut self, reg: Reg8, data: u8) {
+        match reg {
+            Reg8::A => self.a = data,
+            Reg8::B => self.b = data,
+            Reg8::C => self.c = data,
+            Reg8::D => self.d = data,
+            Reg8::E => self.e = data,
+            Reg8::H => self.h = data,
+            Reg8::L => self.l = data,
+        }
+    }
 
-        for name in set {
-            map.insert(name.to_string(), RegisterWrapper::Raw(0x0 as u16));
+    pub fn get_f(&self) -> u8 {
+        self.f
+    }
 
-            let mut chars = name.chars().rev();
+    pub fn put_f(&mut self, data: u8) {
+        self.f = data & 0xF0;
+    }
 
-            // right
-            map.insert(chars.next().unwrap().to_string(), RegisterWrapper::Pointer{
-                origin: name.to_string(),
-                position: 0,
-            });
+    pub fn get_af(&self) -> u16 {
+        ((self.a as u16) << 8) | (self.f as u16)
+    }
 
-            // left
-            map.insert(chars.next().unwrap().to_string(), RegisterWrapper::Pointer{
-                origin: name.to_string(),
-                position: 1,
-            });
+    pub fn put_af(&mut self, data: u16) {
+        self.a = (data >> 8) as u8;
+        self.put_f(data as u8);
+    }
 
-        }
+    pub fn get_bc(&self) -> u16 {
+        ((self.b as u16) << 8) | (self.c as u16)
+    }
 
-        GBRegisterSet{ registers: map }
+    pub fn put_bc(&mut self, data: u16) {
+        self.b = (data >> 8) as u8;
+        self.c = data as u8;
     }
 
-    pub fn get(&self, reg: &String) -> u16 {
+    pub fn get_de(&self) -> u16 {
+        ((self.d as u16) << 8) | (self.e as u16)
+    }
 
-        match self.registers.get(reg) {
-            Some(rw) => {
-                match rw {
-                    &RegisterWrapper::Pointer{ origin: ref og, position: pos } => {
-                        self.get(og) >> (8 * pos)
-                    },
-                    &RegisterWrapper::Raw(data) => data,
-                }
-            },
-            None => {
-                println!("Register not found: {}", reg);
-                0
-            },
-        }
+    pub fn put_de(&mut self, data: u16) {
+        self.d = (data >> 8) as u8;
+        self.e = data as u8;
+    }
 
+    pub fn get_hl(&self) -> u16 {
+        ((self.h as u16) << 8) | (self.l as u16)
     }
 
-    pub fn put(&mut self, reg: &String, data: u16) {
+    pub fn put_hl(&mut self, data: u16) {
+        self.h = (data >> 8) as u8;
+        self.l = data as u8;
+    }
 
-        let mut currentReg = reg.clone();
-        let mut targetPos = 0 as usize;
-        'search: loop {
-            let mut res = self.registers.get_mut(&currentReg);
+    // used by (HL+)
+    pub fn inc_hl(&mut self) {
+        let value = self.get_hl().wrapping_add(1);
+        self.put_hl(value);
+    }
 
-            match res {
-                Some(rw) => {
-                    match rw {
-                        &mut RegisterWrapper::Pointer{ origin: ref orig, position: pos } => {
-                            currentReg = orig.clone();
-                            targetPos = pos;
-                            continue 'search;
-                        },
-                        &mut RegisterWrapper::Raw(ref mut reg_data) => {
+    // used by (HL-)
+    pub fn dec_hl(&mut self) {
+        let value = self.get_hl().wrapping_sub(1);
+        self.put_hl(value);
+    }
+
+    pub fn flag_z(&self) -> bool {
+        self.f & FLAG_Z != 0
+    }
+
+    pub fn flag_n(&self) -> bool {
+        self.f & FLAG_N != 0
+    }
+
+    pub fn flag_h(&self) -> bool {
+        self.f & FLAG_H != 0
+    }
 
-                            if reg.len() == 1 {
-                                // position(0) => right side
-                                // position(1) => left side
-                                let mut target_mask = (0xFF00 as u16) >> (8 * targetPos);
-                                let mut data_shifted = data << (8 * targetPos);
+    pub fn flag_c(&self) -> bool {
+        self.f & FLAG_C != 0
+    }
 
-                                *reg_data &= target_mask;
-                                *reg_data |= data_shifted;
-                            } else {
-                                *reg_data = data;
-                            }
-                            break 'search;
-                        },
-                    }
-                },
-                None => {
-                    println!("Register not found: {}", reg);
-                },
-            };
+    pub fn set_flag_z(&mut self, value: bool) {
+        self.set_flag(FLAG_Z, value);
+    }
 
-        };
+    pub fn set_flag_n(&mut self, value: bool) {
+        self.set_flag(FLAG_N, value);
+    }
 
+    pub fn set_flag_h(&mut self, value: bool) {
+        self.set_flag(FLAG_H, value);
     }
 
-    pub fn inc(&mut self, reg: &String) {
+    pub fn set_flag_c(&mut self, value: bool) {
+        self.set_flag(FLAG_C, value);
+    }
 
-        let mut value = self.get(reg);
-        value += 0x1;
-        self.put(reg, value);
+    // sets all the flags at once
+    pub fn set_flags(&mut self, z: bool, n: bool, h: bool, c: bool) {
+        self.f = 0;
+        self.set_flag(FLAG_Z, z);
+        self.set_flag(FLAG_N, n);
+        self.set_flag(FLAG_H, h);
+        self.set_flag(FLAG_C, c);
+    }
 
+    fn set_flag(&mut self, mask: u8, value: bool) {
+        if value {
+            self.f |= mask;
+        } else {
+            self.f &= !mask;
+        }
     }
 
-    pub fn dec(&mut self, reg: &String) {
+}
 
-        let mut value = self.get(reg);
-        value -= 0x1;
-        self.put(reg, value);
+impl Default for GBRegisterSet {
 
+    fn default() -> GBRegisterSet {
+        GBRegisterSet::new()
     }
 
 }
 
 impl fmt::Debug for GBRegisterSet {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        let mut regs = "GBRegisterSet {\n".to_string();
-
-        for (key, value) in self.registers.iter() {
-            regs += &match value {
-                &RegisterWrapper::Pointer{ origin: ref orig, position: pos } => {
-                    format!("\t{} = Pointer(o: {}, p: {})\n", key, orig, pos)
-                },
-                &RegisterWrapper::Raw(d16) => {
-                    format!("\t{} = 0x{:04X}\n", key, d16)
-                },
-            };
-        }
-
-        regs += "}\n";
-        write!(f, "{}", regs,)
+        write!(f, "GBRegisterSet {{\n\tAF = 0x{:04X}\n\tBC = 0x{:04X}\n\tDE = 0x{:04X}\n\tHL = 0x{:04X}\n\tZ = {} N = {} H = {} C = {}\n}}\n",
+            self.get_af(), self.get_bc(), self.get_de(), self.get_hl(),
+            self.flag_z() as u8, self.flag_n() as u8, self.flag_h() as u8, self.flag_c() as u8)
     }
 }
@@ -7,8 +7,26 @@ use sdl2::keyboard::Keycode;
 use sdl2::render::Renderer as SDLRenderer;
 use sdl2::render::Texture as SDLTexture;
 
+use rust_gameboy::GBButton;
+
 pub enum SDLDisplayEvent {
     Quit,
+    Button { button: GBButton, pressed: bool },
+}
+
+// Keyboard layout of the joypad
+fn keycode_to_button(keycode: Keycode) -> Option<GBButton> {
+    match keycode {
+        Keycode::Right => Some(GBButton::Right),
+        Keycode::Left => Some(GBButton::Left),
+        Keycode::Up => Some(GBButton::Up),
+        Keycode::Down => Some(GBButton::Down),
+        Keycode::X => Some(GBButton::A),
+        Keycode::Z => Some(GBButton::B),
+        Keycode::Backspace => Some(GBButton::Select),
+        Keycode::Return => Some(GBButton::Start),
+        _ => None,
+    }
 }
 
 pub struct SDLDisplay {
@@ -52,6 +70,11 @@ impl SDLDisplay {
 
     }
 
+    // Copies a RGB24 160x144 frame to the screen texture
+    pub fn update(&mut self, framebuffer: &[u8]) {
+        self.texture.update(None, framebuffer, 160 * 3).unwrap();
+    }
+
     pub fn step(&mut self) {
 
         self.renderer.clear();
@@ -66,7 +89,17 @@ impl SDLDisplay {
                 | Event::KeyDown { keycode: Some(Keycode::Escape), .. } => {
                     self.events.push(SDLDisplayEvent::Quit);
                 },
-                // TODO: joystick
+                // key repeats would look like new presses
+                Event::KeyDown { keycode: Some(keycode), repeat: false, .. } => {
+                    if let Some(button) = keycode_to_button(keycode) {
+                        self.events.push(SDLDisplayEvent::Button{ button: button, pressed: true });
+                    }
+                },
+                Event::KeyUp { keycode: Some(keycode), .. } => {
+                    if let Some(button) = keycode_to_button(keycode) {
+                        self.events.push(SDLDisplayEvent::Button{ button: button, pressed: false });
+                    }
+                },
                 _ => {}
             }
         }
@@ -0,0 +1,214 @@
+// References:
+// - http://gbdev.gg8.se/wiki/articles/Timer_and_Divider_Registers
+// - http://gbdev.gg8.se/wiki/articles/Timer_Obscure_Behaviour
+
+// TAC bit 2
+const TAC_ENABLE: u8 = 0x04;
+
+pub struct GBTimer {
+
+    // internal 16 bits divider, incremented every cycle. DIV (FF04) is its upper byte
+    counter: u16,
+    tima: u8, // FF05
+    tma: u8, // FF06
+    tac: u8, // FF07
+    // TIMA overflowed in the last cycle. It reads 0 until it's reloaded from TMA in the next one
+    overflow: bool,
+    // TIMA is being reloaded from TMA in this cycle
+    reloading: bool,
+    interrupt_requested: bool,
+
+}
+
+impl GBTimer {
+
+    pub fn new() -> GBTimer {
+        GBTimer{
+            counter: 0,
+            tima: 0,
+            tma: 0,
+            tac: 0,
+            overflow: false,
+            reloading: false,
+            interrupt_requested: false,
+        }
+    }
+
+    pub fn set_counter(&mut self, counter: u16) {
+        self.counter = counter;
+    }
+
+    // Advances the timer by the cycles of the last instruction
+    pub fn step(&mut self, cycles: usize) {
+        // one machine cycle (4 clocks) at a time
+        for _ in 0..(cycles / 4) {
+            self.tick();
+        }
+    }
+
+    // Returns true once for each overflow (interrupt bit 2)
+    pub fn take_interrupt(&mut self) -> bool {
+        let requested = self.interrupt_requested;
+        self.interrupt_requested = false;
+        requested
+    }
+
+    // FF04-FF07
+    pub fn read(&self, pos: usize) -> u8 {
+        match pos {
+            0xFF04 => (self.counter >> 8) as u8,
+            0xFF05 => self.tima,
+            0xFF06 => self.tma,
+            // only the lower 3 bits exist
+            0xFF07 => self.tac | 0xF8,
+            _ => 0xFF,
+        }
+    }
+
+    // FF04-FF07
+    pub fn write(&mut self, pos: usize, byte: u8) {
+        match pos {
+            // any write resets the whole divider. If the selected bit was set this is a
+            // falling edge, so TIMA is incremented
+            0xFF04 => {
+                let signal = self.signal();
+                self.counter = 0;
+                if signal {
+                    self.increment_tima();
+                }
+            },
+            // the value loaded from TMA wins
+            0xFF05 if !self.reloading => {
+                self.tima = byte;
+                // writing in the cycle after the overflow cancels the reload and the interrupt
+                self.overflow = false;
+            },
+            0xFF06 => {
+                self.tma = byte;
+                // the new value goes straight to TIMA if it's being reloaded
+                if self.reloading {
+                    self.tima = byte;
+                }
+            },
+            // disabling the timer or selecting another frequency can cause a falling edge
+            0xFF07 => {
+                let signal = self.signal();
+                self.tac = byte & 0x07;
+                if signal && !self.signal() {
+                    self.increment_tima();
+                }
+            },
+            _ => {},
+        }
+    }
+
+    fn tick(&mut self) {
+        self.reloading = false;
+
+        if self.overflow {
+            self.overflow = false;
+            self.reloading = true;
+            self.tima = self.tma;
+            self.interrupt_requested = true;
+        }
+
+        // TIMA is incremented on the falling edge of the selected divider bit
+        let signal = self.signal();
+        self.counter = self.counter.wrapping_add(4);
+        if signal && !self.signal() {
+            self.increment_tima();
+        }
+    }
+
+    // divider bit selected by TAC, and'ed with the enable bit
+    fn signal(&self) -> bool {
+        if self.tac & TAC_ENABLE == 0 {
+            return false;
+        }
+
+        let bit = match self.tac & 0x03 {
+            0x00 => 9, // 4096Hz
+            0x01 => 3, // 262144Hz
+            0x02 => 5, // 65536Hz
+            _ => 7, // 16384Hz
+        };
+
+        (self.counter >> bit) & 0x01 != 0
+    }
+
+    fn increment_tima(&mut self) {
+        if self.tima == 0xFF {
+            self.tima = 0;
+            self.overflow = true;
+        } else {
+            self.tima += 1;
+        }
+    }
+
+}
+
+impl Default for GBTimer {
+
+    fn default() -> GBTimer {
+        GBTimer::new()
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::GBTimer;
+
+    // enabled, 262144Hz: TIMA is incremented every 4 machine cycles (divider bit 3)
+    fn fast_timer(tima: u8, tma: u8) -> GBTimer {
+        let mut timer = GBTimer::new();
+        timer.write(0xFF07, 0x05);
+        timer.write(0xFF05, tima);
+        timer.write(0xFF06, tma);
+        timer
+    }
+
+    #[test]
+    fn overflow_reloads_tma_one_cycle_later() {
+        let mut timer = fast_timer(0xFF, 0x42);
+
+        timer.step(16);
+        // TIMA reads 0 for a cycle before the reload
+        assert_eq!(timer.read(0xFF05), 0x00);
+        assert!(!timer.take_interrupt());
+
+        timer.step(4);
+        assert_eq!(timer.read(0xFF05), 0x42);
+        assert!(timer.take_interrupt());
+    }
+
+    #[test]
+    fn tima_write_cancels_the_reload() {
+        let mut timer = fast_timer(0xFF, 0x42);
+
+        timer.step(16);
+        timer.write(0xFF05, 0x10);
+
+        timer.step(4);
+        assert_eq!(timer.read(0xFF05), 0x10);
+        assert!(!timer.take_interrupt());
+    }
+
+    #[test]
+    fn div_write_falling_edge_increments_tima() {
+        let mut timer = fast_timer(0x00, 0x00);
+
+        // divider bit 3 is set, resetting the divider is a falling edge
+        timer.set_counter(0x0008);
+        timer.write(0xFF04, 0x00);
+        assert_eq!(timer.read(0xFF04), 0x00);
+        assert_eq!(timer.read(0xFF05), 0x01);
+
+        // bit 3 clear: no edge
+        timer.set_counter(0x0004);
+        timer.write(0xFF04, 0x00);
+        assert_eq!(timer.read(0xFF05), 0x01);
+    }
+
+}
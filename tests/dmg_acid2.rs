@@ -0,0 +1,79 @@
+// dmg-acid2 (https://github.com/mattcurrie/dmg-acid2): draws a face that only looks right
+// if the background, window and sprites are rendered correctly, including the mid frame
+// register changes. The ROM and the reference image aren't distributed with the emulator,
+// copy them to etc/ and run with `cargo test --test dmg_acid2 -- --ignored`
+
+extern crate rust_gameboy;
+extern crate png;
+
+use std::fs::File;
+
+use rust_gameboy::{GameBoy, GBRenderer, Cartridge};
+
+const ROM_PATH: &str = "etc/dmg-acid2.gb";
+const REFERENCE_PATH: &str = "etc/dmg-acid2.png";
+
+const WIDTH: usize = 160;
+const HEIGHT: usize = 144;
+
+// the test is done after a few frames, then it just loops
+const FRAMES: usize = 60;
+
+// Shade (0 - white, 3 - black) of a gray level, so any set of 4 grays can be compared
+fn shade(level: u8) -> u8 {
+    3 - level / 0x40
+}
+
+// Shades of the reference image
+fn load_reference() -> Vec<u8> {
+    let file = File::open(REFERENCE_PATH).expect(REFERENCE_PATH);
+
+    let mut decoder = png::Decoder::new(file);
+    decoder.set_transformations(png::Transformations::EXPAND | png::Transformations::STRIP_16);
+    let mut reader = decoder.read_info().unwrap();
+
+    let mut data = vec![0; reader.output_buffer_size()];
+    let info = reader.next_frame(&mut data).unwrap();
+    assert_eq!((info.width as usize, info.height as usize), (WIDTH, HEIGHT));
+
+    // gray images only use the first channel
+    let channels = info.color_type.samples();
+    data[..info.buffer_size()].chunks(channels).map(|pixel| shade(pixel[0])).collect()
+}
+
+// Shades of the last frame drawn with the given renderer
+fn run(renderer: GBRenderer) -> Vec<u8> {
+    let cartridge = Cartridge::from_file(ROM_PATH).expect(ROM_PATH);
+
+    let mut gameboy = GameBoy::new();
+    gameboy.load_cartridge(cartridge);
+    gameboy.set_renderer(renderer);
+    gameboy.skip_boot();
+
+    for _ in 0..FRAMES {
+        gameboy.run_frame();
+    }
+
+    gameboy.get_framebuffer().chunks(3).map(|pixel| shade(pixel[0])).collect()
+}
+
+fn check(renderer: GBRenderer) {
+    let reference = load_reference();
+    let frame = run(renderer);
+
+    let wrong = (0..WIDTH * HEIGHT).filter(|&pos| frame[pos] != reference[pos]).collect::<Vec<usize>>();
+    assert!(wrong.is_empty(), "{:?}: {} wrong pixels, the first one at {}x{}",
+        renderer, wrong.len(), wrong.first().map_or(0, |pos| pos % WIDTH), wrong.first().map_or(0, |pos| pos / WIDTH));
+}
+
+#[test]
+#[ignore = "needs etc/dmg-acid2.gb and etc/dmg-acid2.png"]
+fn dmg_acid2_scanline() {
+    check(GBRenderer::Scanline);
+}
+
+#[test]
+#[ignore = "needs etc/dmg-acid2.gb and etc/dmg-acid2.png"]
+fn dmg_acid2_pixel_fifo() {
+    check(GBRenderer::PixelFifo);
+}